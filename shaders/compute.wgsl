@@ -16,6 +16,8 @@ struct InstanceInput {
   @location(2) radius: f32,
   @location(3) position: vec2<f32>,
   @location(4) velocity_or_previous_position: vec2<f32>,
+  // seconds of simulated time the particle is removed at
+  @location(5) expires: f32,
 }
 
 struct Output {
@@ -77,7 +79,40 @@ var<storage, read_write> particles:  array<InstanceInput>;
 struct Simulation {
   spawned_particles: u32,
   dt: f32,
+  gravity: vec2<f32>,
+  bounds_min: vec2<f32>,
+  bounds_max: vec2<f32>,
   bound_radius: f32,
+  damping: f32,
+  max_speed: f32,
+  restitution: f32,
+  static_friction: f32,
+  dynamic_friction: f32,
+  time: f32,
+  force_field_count: u32,
+  force_fields: array<ForceField, 8>,
+  // particles within grab_radius of grab_position follow the cursor by grab_offset
+  grab_position: vec2<f32>,
+  grab_offset: vec2<f32>,
+  grab_velocity: vec2<f32>,
+  grab_radius: f32,
+  grabbing: u32,
+  sink_count: u32,
+  sinks: array<Sink, 8>,
+}
+
+// kind 0 is uniform, 1 radial, 2 vortex, 3 noise and 4 drag, packed by simulation_uniform.rs
+struct ForceField {
+  kind: u32,
+  vector: vec2<f32>,
+  parameters: vec4<f32>,
+}
+
+// kind 0 is a circle with the center in bounds.xy and the radius in bounds.z,
+// 1 a box from bounds.xy to bounds.zw
+struct Sink {
+  kind: u32,
+  bounds: vec4<f32>,
 }
 
 @group(0) @binding(1)
@@ -151,8 +186,11 @@ fn sort_particles_entry(@builtin(global_invocation_id) global_id: vec3<u32>) {
 @compute @workgroup_size(256)
 fn calculate_grid_indexes_entry(@builtin(global_invocation_id) global_id: vec3<u32>) {
     var i = global_id.x;
-    if i < sort.sorting_length {
+    // removed particles are sorted behind the live ones like the padding
+    if i < simulation.spawned_particles && particles[i].radius > 0.0 {
         grid_index[i] = get_cell_index(particles[i].position);
+    } else if i < sort.sorting_length {
+        grid_index[i] = EmptyCell;
     }
 }
 
@@ -335,15 +373,16 @@ fn _collide_cell(cell: vec2<u32>) {
 @workgroup_size(256)
 fn fill_grid_entry(@builtin(global_invocation_id) global_id: vec3<u32>) {
     var i = global_id.x;
-    if i < sort.sorting_length {
+    // the sorted cells rather than the positions, removed particles have no cell
+    if i < simulation.spawned_particles && grid_index[i] != EmptyCell {
         if i == 0 {
-            grid[get_cell_index(particles[0].position)] = 0u;
+            grid[grid_index[0]] = 0u;
         } else {
             let i1 = i -1;
             let i2 = i;
 
-            let cell1 = get_cell_index(particles[i1].position);
-            let cell2 = get_cell_index(particles[i2].position);
+            let cell1 = grid_index[i1];
+            let cell2 = grid_index[i2];
             if cell1 != cell2 {
                 grid[cell2] = i2;
             }
@@ -400,32 +439,66 @@ fn apply_box_constraint_entry(@builtin(global_invocation_id) global_id: vec3<u32
     }
 }
 
+// Same as BoxConstraint::apply, only valid between integrate and finalize_speed
+// while velocity_or_previous_position holds the previous position.
 fn apply_box_constraint(i: u32) {
-    var bound_radius = simulation.bound_radius;
-    var max_length = bound_radius - particles[i].radius;
-
-    if particles[i].position.x > max_length {
-        particles[i].position.x = max_length;
-        //    particles[i].velocity = reflect(velocity, vec2<f32>(-1.0, 0.0));
-    }
-    if particles[i].position.x < -max_length {
-        particles[i].position.x = -max_length;
-        //    particles[i].velocity = reflect(velocity, vec2<f32>(1.0, 0.0));
+    let radius = particles[i].radius;
+    let max_position = simulation.bounds_max - radius;
+    let min_position = simulation.bounds_min + radius;
+    let dt = simulation.dt;
+    let threshold = 2.0 * length(simulation.gravity) * dt;
+    var position = particles[i].position;
+    var previous_position = particles[i].velocity_or_previous_position;
+    let velocity = (position - previous_position) / dt;
+
+    var penetration = vec2<f32>(0.0, 0.0);
+    if position.y < min_position.y {
+        penetration.y = min_position.y - position.y;
+        position.y = min_position.y;
+    }
+    if position.y > max_position.y {
+        penetration.y = position.y - max_position.y;
+        position.y = max_position.y;
+    }
+    if position.x < min_position.x {
+        penetration.x = min_position.x - position.x;
+        position.x = min_position.x;
+    }
+    if position.x > max_position.x {
+        penetration.x = position.x - max_position.x;
+        position.x = max_position.x;
+    }
+
+    let displacement = position - previous_position;
+    if penetration.y > 0.0 {
+        position.x += friction(displacement.x, penetration.y);
+        if abs(velocity.y) > threshold {
+            previous_position.y = position.y + velocity.y * simulation.restitution * dt;
+        }
     }
-    if particles[i].position.y > max_length {
-        particles[i].position.y = max_length;
-        //    particles[i].velocity = reflect(velocity, vec2<f32>(0.0, -1.0));
+    if penetration.x > 0.0 {
+        position.y += friction(displacement.y, penetration.x);
+        if abs(velocity.x) > threshold {
+            previous_position.x = position.x + velocity.x * simulation.restitution * dt;
+        }
     }
-    if particles[i].position.y < -max_length {
-        particles[i].position.y = -max_length;
-        //    particles[i].velocity = reflect(velocity, vec2<f32>(0.0, 1.0));
+    particles[i].position = position;
+    particles[i].velocity_or_previous_position = previous_position;
+}
+
+// The correction friction applies to a tangential slip at a contact penetration deep.
+fn friction(slip: f32, penetration: f32) -> f32 {
+    let length = abs(slip);
+    if length <= simulation.static_friction * penetration {
+        return -slip;
     }
+    return -slip * min(simulation.dynamic_friction * penetration / length, 1.0);
 }
 
 @compute @workgroup_size(256)
 fn naive_collisions_entry(@builtin(global_invocation_id) global_id: vec3<u32>) {
     var i = global_id.x;
-    if i >= simulation.spawned_particles {
+    if i >= simulation.spawned_particles || particles[i].radius <= 0.0 {
         return;
     }
     naive_collisions(i);
@@ -434,7 +507,7 @@ fn naive_collisions_entry(@builtin(global_invocation_id) global_id: vec3<u32>) {
 fn naive_collisions(i: u32) {
     let cell_index = grid_index[i];
     for (var j = 0u; j < simulation.spawned_particles; j = j + 1u) {
-        if i == j {
+        if i == j || particles[j].radius <= 0.0 {
               continue;
         }
         collide(i, j);
@@ -442,6 +515,10 @@ fn naive_collisions(i: u32) {
 }
 
 fn collide(i: u32, j: u32) {
+    // the cell walk can hand out a particle against itself, which used to push it sideways
+    if i == j {
+        return;
+    }
     let p1 = particles[i];
     let p2 = particles[j];
     var direction = p1.position - p2.position;
@@ -462,34 +539,146 @@ fn collide(i: u32, j: u32) {
 @compute @workgroup_size(256)
 fn finalize_speed_entry(@builtin(global_invocation_id) global_id: vec3<u32>) {
     var i = global_id.x;
-    if i < simulation.spawned_particles {
+    if i < simulation.spawned_particles && particles[i].radius > 0.0 {
         finalize_speed(i);
     }
 }
 fn finalize_speed(i: u32) {
 
     var velocity = (particles[i].position - particles[i].velocity_or_previous_position) / simulation.dt;
+    velocity = velocity * max(1.0 - simulation.damping * simulation.dt, 0.0);
     let speed = length(velocity);
     if speed != 0.0 {
         velocity = velocity / speed;
-        velocity = velocity * min(speed, 20.0);
+        velocity = velocity * min(speed, simulation.max_speed);
     }
     particles[i].velocity_or_previous_position = velocity;
 }
 
 fn integrate(i: u32) {
-    var gravity = vec2<f32>(0.0, -9.8);
+    var gravity = simulation.gravity;
     var velocity = particles[i].velocity_or_previous_position + gravity * simulation.dt;
+    let position = particles[i].position;
+    var change = vec2<f32>(0.0, 0.0);
+    for (var f = 0u; f < simulation.force_field_count; f = f + 1u) {
+        change += velocity_change(simulation.force_fields[f], position, velocity);
+    }
+    velocity += change;
     particles[i].velocity_or_previous_position = particles[i].position;
     particles[i].position += velocity * simulation.dt;
 } 
 
+// Runs once per update before the substeps, while the velocities are stored.
+@compute @workgroup_size(256)
+fn grab_entry(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    var i = global_id.x;
+    if i < simulation.spawned_particles && simulation.grabbing != 0u && particles[i].radius > 0.0
+        && distance(particles[i].position, simulation.grab_position) < simulation.grab_radius {
+        particles[i].position += simulation.grab_offset;
+        particles[i].velocity_or_previous_position = simulation.grab_velocity;
+    }
+}
+
 @compute @workgroup_size(256)
 fn update_entry(@builtin(global_invocation_id) global_id: vec3<u32>) {
     var i = global_id.x;
-    if i < simulation.spawned_particles {
+    if i < simulation.spawned_particles && particles[i].radius > 0.0 {
+        // a zero radius marks it removed until the cpu cuts it off the live particles
+        if particles[i].expires <= simulation.time || in_sink(particles[i].position) {
+            particles[i].radius = 0.0;
+            return;
+        }
         integrate(i);
         apply_box_constraint(i);
     }
 }
 
+// Same as Sink::contains.
+fn in_sink(position: vec2<f32>) -> bool {
+    for (var s = 0u; s < simulation.sink_count; s = s + 1u) {
+        let sink = simulation.sinks[s];
+        if sink.kind == 0u {
+            let offset = position - sink.bounds.xy;
+            if dot(offset, offset) < sink.bounds.z * sink.bounds.z {
+                return true;
+            }
+        } else if all(position >= sink.bounds.xy) && all(position < sink.bounds.zw) {
+            return true;
+        }
+    }
+    return false;
+}
+
+// Same as ForceField::velocity_change.
+fn velocity_change(field: ForceField, position: vec2<f32>, velocity: vec2<f32>) -> vec2<f32> {
+    let dt = simulation.dt;
+    let p = field.parameters;
+    switch field.kind {
+        case 0u: {
+            return field.vector * dt;
+        }
+        case 1u: {
+            let offset = field.vector - position;
+            return normalize_or_zero(offset) * (p.y * falloff(offset, p.x) * dt);
+        }
+        case 2u: {
+            let offset = position - field.vector;
+            let perp = vec2<f32>(-offset.y, offset.x);
+            return normalize_or_zero(perp) * (p.y * falloff(offset, p.x) * dt);
+        }
+        case 3u: {
+            let point = position / p.y;
+            let t = simulation.time * p.z;
+            return vec2<f32>(noise(point, t, 0u), noise(point, t, 1u)) * (p.x * dt);
+        }
+        case 4u: {
+            let rate = p.x + p.y * length(velocity);
+            return velocity * (exp(-rate * dt) - 1.0);
+        }
+        default: {
+            return vec2<f32>(0.0, 0.0);
+        }
+    }
+}
+
+fn normalize_or_zero(v: vec2<f32>) -> vec2<f32> {
+    let size = length(v);
+    if size == 0.0 {
+        return vec2<f32>(0.0, 0.0);
+    }
+    return v / size;
+}
+
+fn falloff(offset: vec2<f32>, radius: f32) -> f32 {
+    return max(1.0 - length(offset) / radius, 0.0);
+}
+
+// Value noise in -1..1, kept in sync with force_field.rs.
+fn noise(point: vec2<f32>, time: f32, channel: u32) -> f32 {
+    let cell = floor(point);
+    let frame = floor(time);
+    let x = i32(cell.x);
+    let y = i32(cell.y);
+    let z = i32(frame);
+    let f = smoothstep(vec3<f32>(0.0), vec3<f32>(1.0), vec3<f32>(point - cell, time - frame));
+    var layers: array<f32, 2>;
+    for (var k = 0; k < 2; k = k + 1) {
+        let bottom = mix(hash(x, y, z + k, channel), hash(x + 1, y, z + k, channel), f.x);
+        let top = mix(hash(x, y + 1, z + k, channel), hash(x + 1, y + 1, z + k, channel), f.x);
+        layers[k] = mix(bottom, top, f.y);
+    }
+    return mix(layers[0], layers[1], f.z);
+}
+
+fn hash(x: i32, y: i32, z: i32, channel: u32) -> f32 {
+    var h = (bitcast<u32>(x) * 0x8da6b343u)
+        ^ (bitcast<u32>(y) * 0xd8163841u)
+        ^ (bitcast<u32>(z) * 0xcb1ab31fu)
+        ^ (channel * 0x9e3779b9u);
+    h ^= h >> 16u;
+    h *= 0x7feb352du;
+    h ^= h >> 15u;
+    h *= 0x846ca68bu;
+    h ^= h >> 16u;
+    return f32(h >> 8u) / f32(1u << 23u) - 1.0;
+}
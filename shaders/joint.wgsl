@@ -1,7 +1,6 @@
 struct Camera {
   width: f32,
   height: f32,
-  position: vec2<f32>,
   fov: f32,
 };
 
@@ -22,14 +21,22 @@ struct Output {
 }
 
 fn to_camera_pos(world_pos: vec2<f32>) -> vec2<f32> {
-    return vec2<f32>(
-        (world_pos.x - camera.position.x) * 2 / camera.fov,
-        (world_pos.y - camera.position.y) * 2 / (camera.fov * camera.height / camera.width)
-    );
+    var radius = camera.fov;
+    if camera.width < camera.height {
+        return vec2<f32>(
+            world_pos.x * 2 / radius,
+            world_pos.y * 2 / (radius * camera.height / camera.width)
+        );
+    } else {
+        return vec2<f32>(
+            world_pos.x * 2 / (radius * camera.width / camera.height),
+            world_pos.y * 2 / radius
+        );
+    }
 }
 
 @vertex
-fn vs_arrow(input: Input, instance: InstanceInput) -> Output {
+fn vs_joint(input: Input, instance: InstanceInput) -> Output {
     var length_vector = instance.end - instance.start;
     var norm = normalize(length_vector);
     var width_vector = vec2<f32>(-norm.y, norm.x) * 0.3;
@@ -42,6 +49,6 @@ fn vs_arrow(input: Input, instance: InstanceInput) -> Output {
 }
 
 @fragment
-fn fs_arrow(input: Output) -> @location(0) vec4<f32> {
-    return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+fn fs_joint(input: Output) -> @location(0) vec4<f32> {
+    return vec4<f32>(0.8, 0.8, 0.8, 1.0);
 }
@@ -13,20 +13,26 @@ mod newapp;
 // mod wgpu_utils;
 mod rand;
 
-use newapp::application_handler::Event;
+use clap::Parser;
+use newapp::{application_handler::Event, cli::Args};
 use winit::event_loop::{ControlFlow, EventLoop};
 
 fn main() {
-    pollster::block_on(run())
+    let args = Args::parse();
+    if args.headless {
+        newapp::headless::run(args);
+    } else {
+        pollster::block_on(run(args))
+    }
 }
 
-async fn run() {
+async fn run(args: Args) {
     let event_loop: EventLoop<Event> = EventLoop::with_user_event().build().unwrap();
     let proxy = event_loop.create_proxy();
 
     event_loop.set_control_flow(ControlFlow::Poll);
 
-    let mut app = newapp::application_handler::ApplicationHandlerImpl::new(proxy);
+    let mut app = newapp::application_handler::ApplicationHandlerImpl::new(proxy, args);
 
     event_loop.run_app(&mut app).expect("Failed to run app")
 }
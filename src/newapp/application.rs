@@ -1,9 +1,9 @@
-use std::{sync::Arc, time::Instant};
+use std::{io, path::Path, sync::Arc, time::Instant};
 
 use image::GenericImageView;
 use winit::{
     dpi::{PhysicalPosition, PhysicalSize},
-    event::{ElementState, KeyEvent, WindowEvent},
+    event::{ElementState, KeyEvent, MouseButton, WindowEvent},
     event_loop::EventLoopProxy,
     keyboard::{KeyCode, PhysicalKey},
     window::Window,
@@ -11,31 +11,213 @@ use winit::{
 
 use super::{
     application_handler::Event,
-    gpu_simulation::Simulation,
+    cli::{self, Args},
+    export::{self, Exporter, Record},
+    gpu_simulation::Simulation as GpuSimulation,
     profiler::{self, Profiler},
-    rendering::Renderer,
+    rendering::{
+        camera_uniform::{fov, screen_to_world},
+        Renderer,
+    },
+    simulation::{
+        mouse::Tool,
+        recording::{Input, Session},
+        scene::Scene,
+        snapshot::Snapshot,
+        Simulation,
+    },
     watch_file,
 };
 
+// There is only ever one, boxing either variant would gain nothing.
+#[allow(clippy::large_enum_variant)]
+enum Backend {
+    Cpu {
+        simulation: Simulation,
+        renderer: Renderer,
+        // Applied at the start of the next fixed step so recordings can replay them.
+        inputs: Vec<Input>,
+        session: Session,
+    },
+    Gpu(GpuSimulation),
+}
+
+impl Backend {
+    fn update(&mut self, dt: f32, profiler: &mut Profiler) {
+        match self {
+            Backend::Cpu {
+                simulation,
+                inputs,
+                session,
+                ..
+            } => {
+                session.step(simulation, dt, std::mem::take(inputs), profiler);
+            }
+            Backend::Gpu(simulation) => simulation.update(dt, profiler),
+        }
+    }
+
+    fn render(&mut self, blend: f64, dt: f64) {
+        match self {
+            Backend::Cpu {
+                simulation,
+                renderer,
+                ..
+            } => renderer.render(simulation, blend, dt),
+            Backend::Gpu(simulation) => simulation.render(blend, dt),
+        }
+    }
+
+    fn on_resize(&mut self, size: PhysicalSize<u32>) {
+        match self {
+            Backend::Cpu {
+                simulation,
+                renderer,
+                ..
+            } => renderer.on_resize(size, fov(&simulation.scene().bounds)),
+            Backend::Gpu(simulation) => simulation.on_resize(size),
+        }
+    }
+
+    fn apply_scene(&mut self, scene: Scene) {
+        match self {
+            Backend::Cpu {
+                renderer, inputs, ..
+            } => {
+                renderer.on_resize(renderer.screen_size(), fov(&scene.bounds));
+                inputs.push(Input::ApplyScene(Box::new(scene)));
+            }
+            Backend::Gpu(simulation) => simulation.apply_scene(scene),
+        }
+    }
+
+    fn on_event(&mut self, event: &Event) {
+        match self {
+            Backend::Cpu { renderer, .. } => renderer.on_event(event),
+            Backend::Gpu(simulation) => simulation.on_event(event),
+        }
+    }
+
+    fn on_cursor_moved(&mut self, position: PhysicalPosition<f64>, size: PhysicalSize<u32>) {
+        match self {
+            Backend::Cpu {
+                simulation,
+                renderer,
+                inputs,
+                ..
+            } => {
+                renderer.on_cursor_moved(position);
+                let position = screen_to_world(position, size, fov(&simulation.scene().bounds));
+                inputs.push(Input::MouseMove(position));
+            }
+            Backend::Gpu(simulation) => {
+                simulation.on_cursor_moved(position);
+                let position = screen_to_world(position, size, fov(&simulation.scene().bounds));
+                simulation.on_mouse_move(position);
+            }
+        }
+    }
+
+    fn on_mouse_button(&mut self, pressed: bool) {
+        match self {
+            Backend::Cpu { inputs, .. } => inputs.push(Input::MouseButton(pressed)),
+            Backend::Gpu(simulation) => simulation.on_mouse_button(pressed),
+        }
+    }
+
+    fn select_tool(&mut self, tool: Tool) {
+        match self {
+            Backend::Cpu { inputs, .. } => inputs.push(Input::SelectTool(tool)),
+            Backend::Gpu(simulation) => simulation.select_tool(tool),
+        }
+    }
+
+    fn toggle_collision_detection_mode(&mut self) {
+        if let Backend::Cpu { inputs, .. } = self {
+            inputs.push(Input::ToggleCollisionDetectionMode);
+        }
+    }
+
+    fn records(&self) -> Vec<Record> {
+        match self {
+            Backend::Cpu { simulation, .. } => export::cpu_records(simulation),
+            Backend::Gpu(simulation) => export::gpu_records(&simulation.read_particles()),
+        }
+    }
+
+    fn save_snapshot(&self, path: &Path) -> io::Result<()> {
+        match self {
+            Backend::Cpu { simulation, .. } => simulation.save_snapshot(path),
+            Backend::Gpu(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "snapshots are only supported by the cpu backend",
+            )),
+        }
+    }
+
+    fn load_snapshot(&mut self, path: &Path) -> io::Result<()> {
+        match self {
+            Backend::Cpu {
+                renderer, inputs, ..
+            } => {
+                let snapshot = Snapshot::load(path)?;
+                renderer.on_resize(renderer.screen_size(), fov(&snapshot.scene.bounds));
+                inputs.push(Input::Restore(Box::new(snapshot)));
+                Ok(())
+            }
+            Backend::Gpu(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "snapshots are only supported by the cpu backend",
+            )),
+        }
+    }
+
+    fn save_recording(&self, path: &Path) -> io::Result<()> {
+        match self {
+            Backend::Cpu {
+                session: Session::Recording(recording),
+                ..
+            } => recording.save(path),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "nothing is being recorded",
+            )),
+        }
+    }
+}
+
+const SNAPSHOT_FILE: &str = "snapshot.bin";
+// Select the entries of Tool::ALL in order.
+const TOOL_KEYS: [KeyCode; 5] = [
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit4,
+    KeyCode::Digit5,
+];
+
 pub struct Application {
-    // renderer: Renderer,
-    simulation: Simulation,
+    backend: Backend,
+    args: Args,
     should_exit: bool,
     window: Arc<Window>,
     profiler: Profiler,
     last_displayed_time: Instant,
+    exporter: Option<Exporter>,
 
     fixed_dt: f64,
     max_fixed_dt: f64,
     last_instant: Instant,
     physics_lag: f64,
     frame_count: u32,
+    fixed_update_count: u32,
 }
 
 impl Application {
     pub async fn new(
         event_loop: &winit::event_loop::ActiveEventLoop,
         proxy: &EventLoopProxy<Event>,
+        args: Args,
     ) -> Self {
         let size = event_loop.primary_monitor().unwrap().size();
         let window = Arc::new(
@@ -52,21 +234,46 @@ impl Application {
         let surface = instance
             .create_surface(window.clone())
             .expect("Failed to create surface!");
-        let simulation = Simulation::new(&instance, surface, size, proxy).await;
-        // let renderer = Renderer::new().await;
-        Self {
+        let scene = args.load_scene();
+        watch_file::init_scene(proxy, args.scene_path());
+        let backend = match args.backend {
+            cli::Backend::Cpu => {
+                let (simulation, session) = args.cpu_session(scene);
+                let fov = fov(&simulation.scene().bounds);
+                let renderer = Renderer::new(&instance, surface, size, proxy, fov).await;
+                Backend::Cpu {
+                    simulation,
+                    renderer,
+                    inputs: vec![],
+                    session,
+                }
+            }
+            cli::Backend::Gpu => {
+                if args.snapshot.is_some() || args.record.is_some() || args.replay.is_some() {
+                    println!(
+                        "Snapshots and recordings are only supported by the cpu backend, ignoring"
+                    );
+                }
+                Backend::Gpu(GpuSimulation::new(&instance, surface, size, proxy, scene).await)
+            }
+        };
+        let mut application = Self {
             frame_count: 0,
+            fixed_update_count: 0,
             profiler: Profiler::new(),
             last_displayed_time: Instant::now(),
-            // renderer,
+            exporter: args.exporter(),
             should_exit: false,
-            simulation,
+            backend,
+            args,
             fixed_dt: 0.016666,
             max_fixed_dt: 0.1,
             last_instant: Instant::now(),
             physics_lag: 0.0,
             window,
-        }
+        };
+        application.export_frame();
+        application
     }
 
     pub fn window_event(
@@ -142,22 +349,42 @@ impl Application {
         //     self.profiler.display();
         // }
         self.frame_count += 1;
+        if self
+            .args
+            .frames
+            .is_some_and(|frames| self.frame_count >= frames)
+        {
+            self.should_exit = true;
+        }
     }
 
     pub fn on_resize(&mut self, size: PhysicalSize<u32>) {
-        // self.renderer.on_resize(size);
-        self.simulation.on_resize(size);
+        self.backend.on_resize(size);
     }
 
     pub fn render(&mut self, blend: f64, dt: f64) {
-        // self.renderer.render_gpu(&mut self.simulation, blend, dt);
-        self.simulation.render(blend, dt);
+        self.backend.render(blend, dt);
     }
 
     pub fn before_fixed_updates(&mut self) {}
 
     pub fn fixed_update(&mut self, dt: f32) {
-        self.simulation.update(dt, &mut self.profiler);
+        self.backend.update(dt, &mut self.profiler);
+        self.fixed_update_count += 1;
+        self.export_frame();
+    }
+
+    // Frame n is the state after n fixed updates, the same as in headless runs.
+    fn export_frame(&mut self) {
+        let frame = self.fixed_update_count;
+        let Some(exporter) = self.exporter.as_mut().filter(|it| it.wants(frame)) else {
+            return;
+        };
+        let time = frame as f32 * self.fixed_dt as f32;
+        if let Err(e) = exporter.write_frame(frame, time, &self.backend.records()) {
+            println!("Failed to export frame {frame}, stopping the export: {e}");
+            self.exporter = None;
+        }
     }
 
     pub fn after_fixed_updates(&mut self) {}
@@ -166,12 +393,40 @@ impl Application {
 
     pub fn on_keyboard_input(&mut self, event: KeyEvent) {
         match event {
-            // KeyEvent {
-            //     physical_key: PhysicalKey::Code(KeyCode::KeyC),
-            //     repeat: false,
-            //     state: ElementState::Pressed,
-            //     ..
-            // } => self.simulation.toggle_collision_detection_mode(),
+            KeyEvent {
+                physical_key: PhysicalKey::Code(KeyCode::KeyC),
+                repeat: false,
+                state: ElementState::Pressed,
+                ..
+            } => self.backend.toggle_collision_detection_mode(),
+            KeyEvent {
+                physical_key: PhysicalKey::Code(KeyCode::F5),
+                repeat: false,
+                state: ElementState::Pressed,
+                ..
+            } => match self.backend.save_snapshot(SNAPSHOT_FILE.as_ref()) {
+                Ok(()) => println!("Snapshot saved to {SNAPSHOT_FILE}"),
+                Err(e) => println!("Failed to save snapshot: {e}"),
+            },
+            KeyEvent {
+                physical_key: PhysicalKey::Code(KeyCode::F9),
+                repeat: false,
+                state: ElementState::Pressed,
+                ..
+            } => match self.backend.load_snapshot(SNAPSHOT_FILE.as_ref()) {
+                Ok(()) => println!("Snapshot loaded from {SNAPSHOT_FILE}"),
+                Err(e) => println!("Failed to load snapshot: {e}"),
+            },
+            KeyEvent {
+                physical_key: PhysicalKey::Code(code),
+                repeat: false,
+                state: ElementState::Pressed,
+                ..
+            } if TOOL_KEYS.contains(&code) => {
+                let tool = Tool::ALL[TOOL_KEYS.iter().position(|it| *it == code).unwrap()];
+                println!("Mouse tool: {tool:?}");
+                self.backend.select_tool(tool);
+            }
             KeyEvent {
                 physical_key: PhysicalKey::Code(code),
                 ..
@@ -187,19 +442,45 @@ impl Application {
     }
 
     pub fn on_cursor_moved(&mut self, position: PhysicalPosition<f64>) {
-        // let size = self.renderer.screen_size();
-        // let scale = 100.0 / size.width as f64;
-        // let x = position.x - size.width as f64 / 2.0;
-        // let y = position.y - size.height as f64 / 2.0;
-        // self.simulation
-        //     .on_mouse_move(glam::vec2((x * scale) as f32, -(y * scale) as f32));
+        self.backend
+            .on_cursor_moved(position, self.window.inner_size());
     }
 
-    pub fn on_mouse_input(&mut self, _: winit::event::ElementState, _: winit::event::MouseButton) {}
+    pub fn on_mouse_input(&mut self, state: ElementState, button: MouseButton) {
+        if button == MouseButton::Left {
+            self.backend.on_mouse_button(state.is_pressed());
+        }
+    }
 
     pub fn on_user_event(&mut self, event: &Event) {
-        self.simulation.on_event(event);
-        // self.renderer.on_event(event);
+        if let Event::SceneUpdated(path) = event {
+            // a half edited scene must not take the running one down with it
+            let scene = Scene::load(path).and_then(|scene| {
+                let scene = self.args.apply_overrides(scene);
+                scene.validate()?;
+                Ok(scene)
+            });
+            match scene {
+                Ok(scene) => self.backend.apply_scene(scene),
+                Err(e) => println!("Failed to reload scene {path:?}, keeping the current one: {e}"),
+            }
+        }
+        self.backend.on_event(event);
+    }
+
+    pub fn on_exit(&mut self) {
+        if let (Some(exporter), Some(path)) = (self.exporter.take(), &self.args.export) {
+            match exporter.finish() {
+                Ok(()) => println!("Trajectory exported to {path:?}"),
+                Err(e) => println!("Failed to finish export: {e}"),
+            }
+        }
+        if let Some(path) = &self.args.record {
+            match self.backend.save_recording(path) {
+                Ok(()) => println!("Recording saved to {path:?}"),
+                Err(e) => println!("Failed to save recording: {e}"),
+            }
+        }
     }
 
     pub fn on_file_dropped(&mut self, path: std::path::PathBuf) {
@@ -1,20 +1,28 @@
+use std::path::PathBuf;
+
 use winit::{application::ApplicationHandler, event::WindowEvent, event_loop::EventLoopProxy};
 
-use super::application::Application;
+use super::{application::Application, cli::Args};
 
 pub struct ApplicationHandlerImpl {
     state: Option<Application>,
     proxy: EventLoopProxy<Event>,
+    args: Args,
 }
 
 #[derive(Debug)]
 pub enum Event {
     FileUpdated(&'static str),
+    SceneUpdated(PathBuf),
 }
 
 impl ApplicationHandlerImpl {
-    pub fn new(proxy: EventLoopProxy<Event>) -> Self {
-        Self { state: None, proxy }
+    pub fn new(proxy: EventLoopProxy<Event>, args: Args) -> Self {
+        Self {
+            state: None,
+            proxy,
+            args,
+        }
     }
 
     fn get_state(&mut self) -> &mut Application {
@@ -25,7 +33,12 @@ impl ApplicationHandlerImpl {
 impl ApplicationHandler<Event> for ApplicationHandlerImpl {
     fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
         if self.state.is_none() {
-            self.state = pollster::block_on(Application::new(event_loop, &self.proxy)).into();
+            self.state = pollster::block_on(Application::new(
+                event_loop,
+                &self.proxy,
+                self.args.clone(),
+            ))
+            .into();
         }
     }
 
@@ -41,4 +54,10 @@ impl ApplicationHandler<Event> for ApplicationHandlerImpl {
     fn user_event(&mut self, _: &winit::event_loop::ActiveEventLoop, event: Event) {
         self.get_state().on_user_event(&event);
     }
+
+    fn exiting(&mut self, _: &winit::event_loop::ActiveEventLoop) {
+        if let Some(state) = self.state.as_mut() {
+            state.on_exit();
+        }
+    }
 }
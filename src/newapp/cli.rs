@@ -0,0 +1,173 @@
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+
+use super::{
+    export::{Exporter, Field, Format},
+    gpu_simulation,
+    simulation::{
+        recording::{Recording, Replay, Session},
+        scene::{Scene, DEFAULT_SCENE_FILE},
+        CollisionMode, Simulation,
+    },
+};
+
+#[derive(Parser, Debug, Clone)]
+#[command(about = "Granular particle simulation")]
+pub struct Args {
+    /// Which solver to run
+    #[arg(long, value_enum, default_value_t = Backend::Gpu)]
+    pub backend: Backend,
+
+    /// Scene file, defaults to the backend's own scene
+    #[arg(long)]
+    pub scene: Option<PathBuf>,
+
+    /// Overrides the scene's particle limit, snapshots and replays bring their own scene
+    #[arg(long, conflicts_with_all = ["snapshot", "replay"])]
+    pub particles: Option<usize>,
+
+    /// Overrides the scene's random seed
+    #[arg(long, conflicts_with_all = ["snapshot", "replay"])]
+    pub seed: Option<u64>,
+
+    /// Worker threads used by the cpu backend, defaults to the available cores
+    #[arg(long, default_value_t = default_threads())]
+    pub threads: usize,
+
+    /// Collision scheduling of the cpu backend, overrides the one stored in a snapshot
+    #[arg(long, value_enum)]
+    pub collision: Option<CollisionMode>,
+
+    /// Run without a window
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Exit after this many frames
+    #[arg(long)]
+    pub frames: Option<u32>,
+
+    /// Start the cpu backend from a saved snapshot
+    #[arg(long)]
+    pub snapshot: Option<PathBuf>,
+
+    /// Where headless cpu runs save a snapshot of the final state
+    #[arg(long)]
+    pub save_snapshot: Option<PathBuf>,
+
+    /// Record the inputs of every fixed step of a cpu run to this file
+    #[arg(long, conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+
+    /// Replay a cpu recording and check it against the recorded checksums
+    #[arg(long)]
+    pub replay: Option<PathBuf>,
+
+    /// Where headless runs write the final particle state as csv
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+
+    /// Where the particle trajectory is exported, a frame per fixed step
+    #[arg(long)]
+    pub export: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value_t = Format::Csv)]
+    pub export_format: Format,
+
+    /// Export every nth frame, frame 0 being the initial state
+    #[arg(long, default_value_t = 1)]
+    pub export_stride: u32,
+
+    /// Defaults to position, velocity and radius, plain xyz only has positions
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub export_fields: Vec<Field>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Cpu,
+    Gpu,
+}
+
+fn default_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |it| it.get())
+}
+
+impl Args {
+    pub fn scene_path(&self) -> PathBuf {
+        self.scene.clone().unwrap_or_else(|| match self.backend {
+            Backend::Cpu => DEFAULT_SCENE_FILE.into(),
+            Backend::Gpu => gpu_simulation::SCENE_FILE.into(),
+        })
+    }
+
+    pub fn load_scene(&self) -> Scene {
+        let path = self.scene_path();
+        let scene = match Scene::load(&path) {
+            Ok(scene) => scene,
+            Err(e) if self.scene.is_none() => {
+                println!("Failed to load scene {path:?}: {e}");
+                match self.backend {
+                    Backend::Cpu => Scene::default(),
+                    Backend::Gpu => gpu_simulation::default_scene(),
+                }
+            }
+            Err(e) => {
+                println!("Failed to load scene {path:?}: {e}");
+                std::process::exit(1);
+            }
+        };
+        self.apply_overrides(scene)
+    }
+
+    /// Replays start from the recorded state and ignore the scene and snapshot.
+    pub fn cpu_session(&self, scene: Scene) -> (Simulation, Session) {
+        if let Some(path) = &self.replay {
+            let recording = Recording::load(path)
+                .unwrap_or_else(|e| panic!("Failed to load recording {path:?}: {e}"));
+            let replay = Replay::new(recording);
+            return (replay.simulation(), Session::Replay(replay));
+        }
+        let mut simulation = Simulation::new(scene, self.threads);
+        if let Some(path) = &self.snapshot {
+            simulation
+                .load_snapshot(path)
+                .unwrap_or_else(|e| panic!("Failed to load snapshot {path:?}: {e}"));
+        }
+        if let Some(mode) = self.collision {
+            simulation.set_collision_detection_mode(mode);
+        }
+        let session = match self.record {
+            Some(_) => Session::Recording(Recording::new(&simulation)),
+            None => Session::Live,
+        };
+        (simulation, session)
+    }
+
+    pub fn exporter(&self) -> Option<Exporter> {
+        let path = self.export.as_ref()?;
+        let exporter = Exporter::create(
+            path.clone(),
+            self.export_format,
+            self.export_fields.clone(),
+            self.export_stride,
+        );
+        match exporter {
+            Ok(exporter) => Some(exporter),
+            Err(e) => {
+                println!("Failed to create export {path:?}: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    pub fn apply_overrides(&self, mut scene: Scene) -> Scene {
+        if let Some(particles) = self.particles {
+            scene.max_particles = particles;
+        }
+        if let Some(seed) = self.seed {
+            scene.seed = seed;
+        }
+        scene
+    }
+}
@@ -0,0 +1,409 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use glam::{vec3, Vec2, Vec3};
+
+use super::{gpu_simulation::Particle as GpuParticle, simulation::Simulation};
+
+const MAGIC: [u8; 4] = *b"GZTR";
+pub const TRAJECTORY_VERSION: u32 = 1;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One file per frame, `<stem>.<frame>.csv`
+    Csv,
+    /// A single little-endian stream: magic, version, field mask, then per frame
+    /// its number, time and particle count followed by the selected fields of every particle
+    Binary,
+    /// Plain xyz, positions only
+    Xyz,
+    /// Extended xyz with a `Properties` column description, positions first
+    ExtXyz,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Position,
+    Velocity,
+    Radius,
+    Color,
+    InitialId,
+}
+
+/// Backend independent particle state, gpu particles use their buffer index as id.
+#[derive(Debug, Clone, Copy)]
+pub struct Record {
+    pub initial_id: u32,
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub radius: f32,
+    pub color: Vec3,
+}
+
+pub struct Exporter {
+    path: PathBuf,
+    format: Format,
+    fields: Vec<Field>,
+    stride: u32,
+    // Csv opens a file per frame instead.
+    stream: Option<BufWriter<File>>,
+}
+
+impl Format {
+    pub fn default_fields(self) -> Vec<Field> {
+        match self {
+            Format::Xyz => vec![Field::Position],
+            _ => vec![Field::Position, Field::Velocity, Field::Radius],
+        }
+    }
+}
+
+impl Field {
+    fn csv_columns(self) -> &'static str {
+        match self {
+            Field::Position => "x,y",
+            Field::Velocity => "vx,vy",
+            Field::Radius => "radius",
+            Field::Color => "r,g,b",
+            Field::InitialId => "id",
+        }
+    }
+
+    // Vectors are padded to 3d, most xyz readers expect that.
+    fn xyz_property(self) -> &'static str {
+        match self {
+            Field::Position => "pos:R:3",
+            Field::Velocity => "velo:R:3",
+            Field::Radius => "radius:R:1",
+            Field::Color => "color:R:3",
+            Field::InitialId => "id:I:1",
+        }
+    }
+
+    fn mask_bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+pub fn cpu_records(simulation: &Simulation) -> Vec<Record> {
+    simulation
+        .get_particles()
+        .iter()
+        .map(|it| {
+            let color = simulation
+                .colors
+                .get(it.initial_id)
+                .map_or(Vec3::ONE, |it| vec3(it.r, it.g, it.b));
+            Record {
+                initial_id: it.initial_id as u32,
+                position: it.position,
+                velocity: it.velocity,
+                radius: it.radius,
+                color,
+            }
+        })
+        .collect()
+}
+
+pub fn gpu_records(particles: &[GpuParticle]) -> Vec<Record> {
+    particles
+        .iter()
+        .enumerate()
+        // removed particles stay in the buffer with a zero radius until it is compacted
+        .filter(|(_, it)| it.radius > 0.0)
+        .map(|(i, it)| Record {
+            initial_id: i as u32,
+            position: it.position,
+            velocity: it.velocity,
+            radius: it.radius,
+            color: it.color,
+        })
+        .collect()
+}
+
+impl Exporter {
+    /// No fields means the format's default ones.
+    pub fn create(
+        path: PathBuf,
+        format: Format,
+        mut fields: Vec<Field>,
+        stride: u32,
+    ) -> io::Result<Self> {
+        if fields.is_empty() {
+            fields = format.default_fields();
+        }
+        let not_position = fields.iter().find(|it| **it != Field::Position);
+        if let (Format::Xyz, Some(field)) = (format, not_position) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("plain xyz only has positions, use ext-xyz to export {field:?}"),
+            ));
+        }
+        // xyz readers take the first column for the position
+        if let Format::Xyz | Format::ExtXyz = format {
+            fields.retain(|it| *it != Field::Position);
+            fields.insert(0, Field::Position);
+        }
+        let stream = match format {
+            Format::Csv => None,
+            Format::Binary => {
+                let mut file = BufWriter::new(File::create(&path)?);
+                file.write_all(&MAGIC)?;
+                file.write_all(&TRAJECTORY_VERSION.to_le_bytes())?;
+                let mask = fields.iter().fold(0, |mask, it| mask | it.mask_bit());
+                file.write_all(&mask.to_le_bytes())?;
+                Some(file)
+            }
+            Format::Xyz | Format::ExtXyz => Some(BufWriter::new(File::create(&path)?)),
+        };
+        Ok(Self {
+            path,
+            format,
+            fields,
+            stride: stride.max(1),
+            stream,
+        })
+    }
+
+    pub fn wants(&self, frame: u32) -> bool {
+        frame.is_multiple_of(self.stride)
+    }
+
+    pub fn write_frame(&mut self, frame: u32, time: f32, records: &[Record]) -> io::Result<()> {
+        match (self.format, &mut self.stream) {
+            (Format::Csv, _) => write_csv(&frame_path(&self.path, frame), &self.fields, records),
+            (Format::Binary, Some(stream)) => {
+                stream.write_all(&frame.to_le_bytes())?;
+                stream.write_all(&time.to_le_bytes())?;
+                stream.write_all(&(records.len() as u32).to_le_bytes())?;
+                // Always in declaration order so the mask is enough to decode a frame.
+                for record in records {
+                    for field in Field::value_variants() {
+                        if self.fields.contains(field) {
+                            write_binary_field(stream, *field, record)?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            (Format::Xyz, Some(stream)) => {
+                writeln!(stream, "{}", records.len())?;
+                writeln!(stream, "frame {frame} time {time}")?;
+                for record in records {
+                    writeln!(stream, "P {} {} 0", record.position.x, record.position.y)?;
+                }
+                Ok(())
+            }
+            (Format::ExtXyz, Some(stream)) => {
+                let properties = std::iter::once("species:S:1")
+                    .chain(self.fields.iter().map(|it| it.xyz_property()))
+                    .collect::<Vec<_>>()
+                    .join(":");
+                writeln!(stream, "{}", records.len())?;
+                writeln!(
+                    stream,
+                    "Properties={properties} Time={time} Frame={frame} pbc=\"F F F\""
+                )?;
+                for record in records {
+                    let values = text_values(&self.fields, record, true);
+                    writeln!(stream, "P {}", values.join(" "))?;
+                }
+                Ok(())
+            }
+            (_, None) => unreachable!("stream formats always open their file"),
+        }
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        match self.stream {
+            Some(mut stream) => stream.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+pub fn write_csv(path: &Path, fields: &[Field], records: &[Record]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let header = fields
+        .iter()
+        .map(|it| it.csv_columns())
+        .collect::<Vec<_>>()
+        .join(",");
+    writeln!(file, "{header}")?;
+    for record in records {
+        writeln!(file, "{}", text_values(fields, record, false).join(","))?;
+    }
+    file.flush()
+}
+
+fn frame_path(path: &Path, frame: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().unwrap_or("csv".as_ref()).to_string_lossy();
+    path.with_file_name(format!("{stem}.{frame:06}.{extension}"))
+}
+
+fn text_values(fields: &[Field], record: &Record, pad_to_3d: bool) -> Vec<String> {
+    let mut values = vec![];
+    let push_vector = |values: &mut Vec<String>, value: Vec2| {
+        values.push(value.x.to_string());
+        values.push(value.y.to_string());
+        if pad_to_3d {
+            values.push("0".to_string());
+        }
+    };
+    for field in fields {
+        match field {
+            Field::Position => push_vector(&mut values, record.position),
+            Field::Velocity => push_vector(&mut values, record.velocity),
+            Field::Radius => values.push(record.radius.to_string()),
+            Field::Color => values.extend(record.color.to_array().map(|it| it.to_string())),
+            Field::InitialId => values.push(record.initial_id.to_string()),
+        }
+    }
+    values
+}
+
+fn write_binary_field(out: &mut impl Write, field: Field, record: &Record) -> io::Result<()> {
+    match field {
+        Field::Position => write_floats(out, &record.position.to_array()),
+        Field::Velocity => write_floats(out, &record.velocity.to_array()),
+        Field::Radius => write_floats(out, &[record.radius]),
+        Field::Color => write_floats(out, &record.color.to_array()),
+        Field::InitialId => out.write_all(&record.initial_id.to_le_bytes()),
+    }
+}
+
+fn write_floats(out: &mut impl Write, values: &[f32]) -> io::Result<()> {
+    for value in values {
+        out.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use glam::{vec2, vec3, Vec3};
+
+    use super::{Exporter, Field, Format, Record, MAGIC, TRAJECTORY_VERSION};
+
+    fn records() -> Vec<Record> {
+        vec![
+            Record {
+                initial_id: 7,
+                position: vec2(1.0, 2.0),
+                velocity: vec2(-3.0, 4.5),
+                radius: 0.5,
+                color: vec3(0.1, 0.2, 0.3),
+            },
+            Record {
+                initial_id: 2,
+                position: vec2(-6.0, 8.25),
+                velocity: vec2(0.0, -1.0),
+                radius: 1.0,
+                color: Vec3::ONE,
+            },
+        ]
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("gamez-export-{}-{name}", std::process::id()))
+    }
+
+    fn floats(bytes: &[u8]) -> Vec<f32> {
+        bytes
+            .chunks_exact(4)
+            .map(|it| f32::from_le_bytes(it.try_into().unwrap()))
+            .collect()
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn binary_frames_follow_header_and_mask() {
+        let path = temp_path("trajectory.bin");
+        // Deliberately out of declaration order, the stream must not depend on it.
+        let fields = vec![Field::Radius, Field::Position];
+        let mut exporter = Exporter::create(path.clone(), Format::Binary, fields, 1).unwrap();
+        exporter.write_frame(0, 0.0, &records()).unwrap();
+        exporter.write_frame(1, 0.25, &records()[..1]).unwrap();
+        exporter.finish().unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(bytes[..4], MAGIC);
+        assert_eq!(u32_at(&bytes, 4), TRAJECTORY_VERSION);
+        assert_eq!(
+            u32_at(&bytes, 8),
+            Field::Position.mask_bit() | Field::Radius.mask_bit()
+        );
+
+        // frame, time and count, then x, y, radius per particle
+        let first = &bytes[12..12 + 12 + 2 * 12];
+        assert_eq!(u32_at(first, 0), 0);
+        assert_eq!(floats(&first[4..8]), [0.0]);
+        assert_eq!(u32_at(first, 8), 2);
+        assert_eq!(floats(&first[12..]), [1.0, 2.0, 0.5, -6.0, 8.25, 1.0]);
+
+        let second = &bytes[12 + first.len()..];
+        assert_eq!(second.len(), 12 + 12);
+        assert_eq!(u32_at(second, 0), 1);
+        assert_eq!(floats(&second[4..8]), [0.25]);
+        assert_eq!(u32_at(second, 8), 1);
+        assert_eq!(floats(&second[12..]), [1.0, 2.0, 0.5]);
+    }
+
+    #[test]
+    fn csv_columns_follow_selected_fields() {
+        let path = temp_path("frames.csv");
+        let fields = vec![Field::Radius, Field::Position, Field::InitialId];
+        let mut exporter = Exporter::create(path.clone(), Format::Csv, fields, 1).unwrap();
+        exporter.write_frame(3, 0.05, &records()).unwrap();
+        exporter.finish().unwrap();
+        let frame = path.with_file_name(format!(
+            "gamez-export-{}-frames.000003.csv",
+            std::process::id()
+        ));
+        let text = fs::read_to_string(&frame).unwrap();
+        fs::remove_file(&frame).unwrap();
+
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines, ["radius,x,y,id", "0.5,1,2,7", "1,-6,8.25,2"]);
+    }
+
+    #[test]
+    fn xyz_formats_write_positions_first() {
+        let path = temp_path("trajectory.xyz");
+        let fields = vec![Field::Radius, Field::InitialId];
+        let mut exporter = Exporter::create(path.clone(), Format::ExtXyz, fields, 1).unwrap();
+        exporter.write_frame(2, 0.5, &records()[1..]).unwrap();
+        exporter.finish().unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "1");
+        assert!(lines[1].starts_with("Properties=species:S:1:pos:R:3:radius:R:1:id:I:1 "));
+        assert_eq!(lines[2], "P -6 8.25 0 1 2");
+    }
+
+    #[test]
+    fn plain_xyz_rejects_other_fields() {
+        let path = temp_path("rejected.xyz");
+        let fields = vec![Field::Position, Field::Velocity];
+        let error = Exporter::create(path.clone(), Format::Xyz, fields, 1).err();
+        assert_eq!(error.unwrap().kind(), std::io::ErrorKind::InvalidInput);
+        assert!(!path.exists());
+
+        let exporter = Exporter::create(path.clone(), Format::Xyz, vec![], 1).unwrap();
+        exporter.finish().unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,22 +1,35 @@
 mod simulation_uniform;
-use std::mem;
+use std::{f32::consts::PI, mem};
 
 use glam::{uvec2, vec2, vec3, UVec2, Vec2, Vec3};
 use rand::{rngs::StdRng, Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
-use simulation_uniform::SimulationUniform;
+use simulation_uniform::{SimulationUniform, MAX_FORCE_FIELDS, MAX_SINKS};
 use wgpu::util::{DeviceExt, RenderEncoder};
 use wgpu_profiler::*;
-use winit::{dpi::PhysicalSize, event_loop::EventLoopProxy};
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    event_loop::EventLoopProxy,
+};
 
 use crate::rand::MyRng;
 
 use super::{
     application_handler::Event,
     rendering::{
-        camera_uniform::CameraUniform, square_mesh::SquareMesh, wgpu_utils::round_buffer_size,
+        camera_uniform::{fov, CameraUniform},
+        mouse::MouseRenderer,
+        square_mesh::SquareMesh,
+        wgpu_utils::round_buffer_size,
+    },
+    simulation::{
+        box_constraint::BoxConstraint,
+        emitter::{EmitterState, Shape},
+        mouse::{Mouse, MouseTools, Tool},
+        scene::Scene,
+        spatial_hash::fixed_size_grid::FixedSizeGrid,
+        Color,
     },
-    simulation::{box_constraint::BoxConstraint, spatial_hash::fixed_size_grid::FixedSizeGrid},
     utils::wgpu_profiler::print_wgpu_profiler_result,
     watch_file,
 };
@@ -34,17 +47,21 @@ struct Sort {
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, Pod, Zeroable)]
-struct Particle {
+pub struct Particle {
     pub color: Vec3,
     pub radius: f32,
     pub position: Vec2,
     pub velocity: Vec2,
+    /// Seconds of simulated time the particle is removed at
+    pub expires: f32,
+    _padding: [f32; 3],
 }
 
 impl Particle {
     fn desc() -> wgpu::VertexBufferLayout<'static> {
-        const ATTRS: [wgpu::VertexAttribute; 4] =
-            wgpu::vertex_attr_array![1 => Float32x3, 2 => Float32, 3 => Float32x2, 4 => Float32x2];
+        const ATTRS: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+            1 => Float32x3, 2 => Float32, 3 => Float32x2, 4 => Float32x2, 5 => Float32
+        ];
         wgpu::VertexBufferLayout {
             array_stride: mem::size_of::<Self>() as wgpu::BufferAddress,
             step_mode: wgpu::VertexStepMode::Instance,
@@ -57,14 +74,16 @@ pub struct Simulation {
     device: wgpu::Device,
     queue: wgpu::Queue,
     surface_config: wgpu::SurfaceConfiguration,
-    surface: wgpu::Surface<'static>,
+    surface: Option<wgpu::Surface<'static>>,
     square_mesh: SquareMesh,
     camera_uniform: CameraUniform,
+    mouse_renderer: MouseRenderer,
     main_bind_group_layout: wgpu::BindGroupLayout,
     main_bind_group: wgpu::BindGroup,
     shader_module: wgpu::ShaderModule,
-    particles: Box<[Particle; COUNT]>,
+    particles: Vec<Particle>,
     spawned_particles: u32,
+    capacity: u32,
     instance_buffer: wgpu::Buffer,
     render_pipeline: wgpu::RenderPipeline,
     compute_pipeline: ComputePipeline,
@@ -72,21 +91,49 @@ pub struct Simulation {
     compute_bind_group_layout: wgpu::BindGroupLayout,
     simulation_uniform: SimulationUniform,
     update_count: u64,
+    // seconds simulated, for the force fields
+    time: f64,
     grid_buffer: wgpu::Buffer,
     sort_buffer: wgpu::Buffer,
     grid: FixedSizeGrid,
     grid_index_buffer: wgpu::Buffer,
     gpu_profiler: GpuProfiler,
+    scene: Scene,
+    mouse: Mouse,
+    // places painted and emitted particles
+    rng: StdRng,
+    emitters: Vec<EmitterState>,
 }
 
 const GROUP_SIZE: u32 = 256;
 const GRID_GROUP_SIZE: u32 = 16;
 const COUNT: usize = 1 << 13;
 const MAX_PARTICLE_RADIUS: f32 = 0.5;
+// expiry of particles without a lifetime
+const NEVER: f32 = f32::MAX;
+// updates between cutting off the particles removed by sinks and lifetimes
+const COMPACT_INTERVAL: u64 = 10;
 const SHADER_FILE: &'static str = "shaders/compute.wgsl";
+pub const SCENE_FILE: &str = "scenes/gpu.ron";
 
 const BOUND_RADIUS: u32 = 3 * 13;
-const FOV: f32 = BOUND_RADIUS as f32 * 2.0;
+
+pub fn default_scene() -> Scene {
+    Scene {
+        bounds: BoxConstraint::around_center(BOUND_RADIUS as f32),
+        gravity: vec2(0.0, -9.8),
+        damping: 0.0,
+        max_speed: 20.0,
+        substeps: 8,
+        max_particles: COUNT,
+        emitters: vec![],
+        mouse: MouseTools {
+            paint_radius: (0.3, MAX_PARTICLE_RADIUS),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
 
 fn get_particle_buffer_size() -> wgpu::BufferAddress {
     round_buffer_size((COUNT as usize * mem::size_of::<Particle>()) as wgpu::BufferAddress)
@@ -98,27 +145,51 @@ impl Simulation {
         surface: wgpu::Surface<'static>,
         size: PhysicalSize<u32>,
         proxy: &EventLoopProxy<Event>,
+        scene: Scene,
     ) -> Self {
         watch_file::init(proxy, "shaders");
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                force_fallback_adapter: false,
-                compatible_surface: Some(&surface),
-            })
-            .await
-            .expect("Failed to find an appropriate adapter");
+        Self::create(instance, Some(surface), size, scene).await
+    }
+
+    // Runs on a software adapter when one is available so it works on machines without a gpu.
+    pub async fn new_headless(instance: &wgpu::Instance, scene: Scene) -> Self {
+        Self::create(instance, None, PhysicalSize::new(1, 1), scene).await
+    }
+
+    async fn create(
+        instance: &wgpu::Instance,
+        surface: Option<wgpu::Surface<'static>>,
+        size: PhysicalSize<u32>,
+        scene: Scene,
+    ) -> Self {
+        warn_unsupported(&scene);
+        let mut adapter_options = wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            force_fallback_adapter: surface.is_none(),
+            compatible_surface: surface.as_ref(),
+        };
+        let adapter = match instance.request_adapter(&adapter_options).await {
+            Some(adapter) => adapter,
+            None => {
+                adapter_options.force_fallback_adapter = false;
+                instance
+                    .request_adapter(&adapter_options)
+                    .await
+                    .expect("Failed to find an appropriate adapter")
+            }
+        };
+        println!("Using adapter {:?}", adapter.get_info().name);
 
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
-                    required_features: wgpu::Features::empty()
-                        | wgpu::Features::TIMESTAMP_QUERY
-                        | wgpu::Features::TIMESTAMP_QUERY_INSIDE_PASSES
-                        | wgpu::Features::TIMESTAMP_QUERY_INSIDE_ENCODERS
-                        | wgpu::Features::VERTEX_WRITABLE_STORAGE,
-                    required_limits: Default::default(),
+                    required_features: adapter.features()
+                        & (wgpu::Features::TIMESTAMP_QUERY
+                            | wgpu::Features::TIMESTAMP_QUERY_INSIDE_PASSES
+                            | wgpu::Features::TIMESTAMP_QUERY_INSIDE_ENCODERS
+                            | wgpu::Features::VERTEX_WRITABLE_STORAGE),
+                    required_limits: adapter.limits(),
                     memory_hints: Default::default(),
                 },
                 None,
@@ -126,49 +197,72 @@ impl Simulation {
             .await
             .expect("Failed to create device");
 
-        let swapchain_capabilities = surface.get_capabilities(&adapter);
-        let selected_format = wgpu::TextureFormat::Bgra8Unorm;
-        let swapchain_format = swapchain_capabilities
-            .formats
-            .iter()
-            .find(|d| **d == selected_format)
-            .expect("failed to select proper surface texture format!");
-
-        let surface_config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: *swapchain_format,
-            width: size.width,
-            height: size.height,
-            present_mode: wgpu::PresentMode::AutoVsync,
-            desired_maximum_frame_latency: 0,
-            alpha_mode: swapchain_capabilities.alpha_modes[0],
-            view_formats: vec![],
-        };
+        let surface_config = match &surface {
+            Some(surface) => {
+                let swapchain_capabilities = surface.get_capabilities(&adapter);
+                let selected_format = wgpu::TextureFormat::Bgra8Unorm;
+                let swapchain_format = swapchain_capabilities
+                    .formats
+                    .iter()
+                    .find(|d| **d == selected_format)
+                    .expect("failed to select proper surface texture format!");
 
-        surface.configure(&device, &surface_config);
+                let surface_config = wgpu::SurfaceConfiguration {
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                    format: *swapchain_format,
+                    width: size.width,
+                    height: size.height,
+                    present_mode: wgpu::PresentMode::AutoVsync,
+                    desired_maximum_frame_latency: 0,
+                    alpha_mode: swapchain_capabilities.alpha_modes[0],
+                    view_formats: vec![],
+                };
+                surface.configure(&device, &surface_config);
+                surface_config
+            }
+            None => wgpu::SurfaceConfiguration {
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                format: wgpu::TextureFormat::Bgra8Unorm,
+                width: size.width,
+                height: size.height,
+                present_mode: wgpu::PresentMode::AutoVsync,
+                desired_maximum_frame_latency: 0,
+                alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+                view_formats: vec![],
+            },
+        };
 
         let shader_module = load_shader(&device);
 
-        let camera_uniform = CameraUniform::new(&device, size, FOV);
+        let camera_uniform = CameraUniform::new(&device, size, fov(&scene.bounds));
 
         let square_mesh = SquareMesh::new(&device);
+        let mouse_renderer = MouseRenderer::new(&device, surface_config.format, &camera_uniform);
 
-        let mut rng = MyRng::new();
-        let mut particles: Box<[Particle; COUNT]> = Box::from(
-            [Particle {
+        let mut rng = MyRng::with_seed(scene.seed);
+        // the bitonic sort needs a power of two, padding particles are sorted past the live ones
+        let capacity = scene.max_particles.max(1).next_power_of_two();
+        // emitters fill the box over time, without any it starts out full
+        let count = if scene.emitters.is_empty() {
+            scene.max_particles.max(1)
+        } else {
+            0
+        };
+        let mut particles = vec![
+            Particle {
                 color: vec3(0.0, 0.0, 0.0),
                 position: vec2(0.0, 5.0),
                 velocity: vec2(0.0, 0.0),
                 radius: 0.0,
-            }; COUNT],
-        );
-        let grid = FixedSizeGrid::new(
-            MAX_PARTICLE_RADIUS * 2.0,
-            BoxConstraint::around_center(BOUND_RADIUS as f32),
-        );
+                expires: NEVER,
+                _padding: [0.0; 3],
+            };
+            capacity
+        ];
+        let grid = FixedSizeGrid::new(MAX_PARTICLE_RADIUS * 2.0, scene.bounds);
         dbg!(&grid);
-        for (i, particle) in particles.as_mut().iter_mut().enumerate() {
-            let i = COUNT - i - 1;
+        for (i, particle) in particles[..count].iter_mut().enumerate() {
+            let i = count - i - 1;
             *particle = Particle {
                 color: vec3(1.0, 1.0, 0.0),
                 // color: vec3(1.0, 1.0, 1.0) * i as f32 / COUNT as f32,
@@ -186,38 +280,26 @@ impl Simulation {
                 velocity: vec2(0.0, 0.0),
                 radius: rng.get_random_size(0.6..=1.0) * MAX_PARTICLE_RADIUS,
                 //radius: MAX_PARTICLE_RADIUS,
+                expires: NEVER,
+                _padding: [0.0; 3],
             };
         }
 
         let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("SimulationInstanceBuffer"),
-            contents: bytemuck::cast_slice(particles.as_ref()),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
-        });
-        let grid_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("GridBuffer"),
-            // contents: bytemuck::cast_slice(&[0u32; (grid.size.x * grid.size.y) as usize]),
-            usage: wgpu::BufferUsages::STORAGE,
-            size: (mem::size_of::<u32>() as u32 * grid.size.x * grid.size.y) as u64,
-            mapped_at_creation: false,
+            contents: bytemuck::cast_slice(&particles),
+            usage: wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
         });
+        let (grid_buffer, sort_buffer) = create_grid_buffers(&device, &grid, capacity as u32);
         let grid_index_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("GridIndexBuffer"),
             usage: wgpu::BufferUsages::STORAGE,
-            size: (mem::size_of::<u32>() * COUNT) as u64,
+            size: (mem::size_of::<u32>() * capacity) as u64,
             mapped_at_creation: false,
         });
-        let sort_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("SortBuffer"),
-            contents: bytemuck::cast_slice(&[Sort {
-                pass_index: 0,
-                sorting_length: COUNT as u32,
-                grid_size: grid.size,
-                cell_size: grid.cell_size,
-                origin: grid.origin,
-            }]),
-            usage: wgpu::BufferUsages::UNIFORM,
-        });
 
         let simulation_uniform = SimulationUniform::new(&device);
 
@@ -300,32 +382,15 @@ impl Simulation {
                 resource: camera_uniform.get_binding_resource(),
             }],
         });
-        let compute_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("ComputeBindGroup"),
-            layout: &compute_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: instance_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: simulation_uniform.get_binding_resource(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: grid_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 3,
-                    resource: sort_buffer.as_entire_binding(),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 4,
-                    resource: grid_index_buffer.as_entire_binding(),
-                },
-            ],
-        });
+        let compute_bind_group = create_compute_bind_group(
+            &device,
+            &compute_bind_group_layout,
+            &instance_buffer,
+            &simulation_uniform,
+            &grid_buffer,
+            &sort_buffer,
+            &grid_index_buffer,
+        );
 
         let (render_pipeline, compute_pipeline) = create_pipeline(
             &device,
@@ -344,14 +409,16 @@ impl Simulation {
             shader_module,
             square_mesh,
             camera_uniform,
+            mouse_renderer,
             device,
             queue,
             surface_config,
             main_bind_group,
             main_bind_group_layout,
             particles,
-            spawned_particles: COUNT as u32,
+            spawned_particles: count as u32,
             // spawned_particles: 1,
+            capacity: capacity as u32,
             instance_buffer,
             grid_buffer,
             grid_index_buffer,
@@ -362,7 +429,40 @@ impl Simulation {
             compute_bind_group_layout,
             simulation_uniform,
             update_count: 0,
+            time: 0.0,
+            mouse: Mouse::default(),
+            rng: StdRng::seed_from_u64(scene.seed),
+            emitters: vec![],
+            scene,
+        }
+    }
+
+    pub fn apply_scene(&mut self, scene: Scene) {
+        warn_unsupported(&scene);
+        if scene.max_particles > self.capacity as usize {
+            println!(
+                "The gpu backend keeps its initial capacity of {} particles, restart to raise it",
+                self.capacity
+            );
+        }
+        if scene.bounds != self.scene.bounds {
+            self.grid = FixedSizeGrid::new(MAX_PARTICLE_RADIUS * 2.0, scene.bounds);
+            (self.grid_buffer, self.sort_buffer) =
+                create_grid_buffers(&self.device, &self.grid, self.capacity);
+            self.compute_bind_group = create_compute_bind_group(
+                &self.device,
+                &self.compute_bind_group_layout,
+                &self.instance_buffer,
+                &self.simulation_uniform,
+                &self.grid_buffer,
+                &self.sort_buffer,
+                &self.grid_index_buffer,
+            );
+            let size = PhysicalSize::new(self.surface_config.width, self.surface_config.height);
+            self.camera_uniform
+                .on_resize(&self.queue, size, fov(&scene.bounds));
         }
+        self.scene = scene;
     }
 
     pub fn update(&mut self, dt: f32, profiler: &mut super::profiler::Profiler) {
@@ -371,17 +471,34 @@ impl Simulation {
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
 
-        let substeps = 8;
+        let substeps = self.scene.substeps.max(1);
         let dt = dt / 4.0 / substeps as f32;
 
+        self.compact();
+        self.spawn();
+        self.apply_mouse_tools();
         self.simulation_uniform.update(
             &self.device,
             &mut encoder,
             &self.queue,
             self.spawned_particles as u32,
-            BOUND_RADIUS as f32,
+            &self.scene,
+            &self.mouse,
+            self.time as f32,
             dt,
         );
+        self.time += (dt * substeps as f32) as f64;
+        self.mouse.moved();
+
+        if self.mouse.is_using(Tool::Grab) {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("grab"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_bind_group(0, &self.compute_bind_group, &[]);
+            compute_pass.set_pipeline(&self.compute_pipeline.grab);
+            compute_pass.dispatch_workgroups(self.spawned_particles.div_ceil(GROUP_SIZE), 1, 1);
+        }
 
         {
             for s in 0..substeps {
@@ -396,17 +513,13 @@ impl Simulation {
                     let mut compute_pass = scope.scoped_compute_pass("calc index", &self.device);
                     compute_pass.set_bind_group(0, &self.compute_bind_group, &[]);
                     compute_pass.set_pipeline(&self.compute_pipeline.calculate_grid_indexes);
-                    compute_pass.dispatch_workgroups(
-                        self.spawned_particles.div_ceil(GROUP_SIZE),
-                        1,
-                        1,
-                    );
+                    compute_pass.dispatch_workgroups(self.capacity.div_ceil(GROUP_SIZE), 1, 1);
                     drop(compute_pass);
                     let mut compute_pass = scope.scoped_compute_pass("sort", &self.device);
                     compute_pass.set_bind_group(0, &self.compute_bind_group, &[]);
                     compute_pass.set_pipeline(&self.compute_pipeline.sort);
                     compute_pass.dispatch_workgroups(
-                        self.spawned_particles.div_ceil(GROUP_SIZE * 2),
+                        self.capacity.div_ceil(GROUP_SIZE * 2),
                         1,
                         1,
                     );
@@ -503,16 +616,169 @@ impl Simulation {
         self.update_count += 1;
     }
 
+    pub fn on_mouse_move(&mut self, position: Vec2) {
+        self.mouse.position = position;
+    }
+
+    pub fn on_cursor_moved(&mut self, position: PhysicalPosition<f64>) {
+        self.mouse_renderer.on_cursor_moved(position);
+    }
+
+    pub fn on_mouse_button(&mut self, pressed: bool) {
+        self.mouse.pressed = pressed;
+    }
+
+    pub fn select_tool(&mut self, tool: Tool) {
+        self.mouse.tool = tool;
+    }
+
+    pub fn scene(&self) -> &Scene {
+        &self.scene
+    }
+
+    // Appends the bursts behind the live particles, which the sort keeps in front of the padding.
+    fn spawn(&mut self) {
+        self.emitters
+            .resize(self.scene.emitters.len(), EmitterState::default());
+        let limit = (self.scene.max_particles as u32).min(self.capacity);
+        for i in 0..self.scene.emitters.len() {
+            let room = limit.saturating_sub(self.spawned_particles) as usize;
+            let emitter = &self.scene.emitters[i];
+            let count = self.emitters[i].due(emitter, self.time).min(room);
+            if count == 0 {
+                continue;
+            }
+            // areas look for free spots, which needs the particles back from the gpu
+            let particles = match emitter.shape {
+                Shape::Area { .. } => self.read_particles(),
+                _ => vec![],
+            };
+            let spawns = emitter.burst(self.time, count, &mut self.rng, |position, radius| {
+                particles
+                    .iter()
+                    .all(|it| position.distance(it.position) >= radius + it.radius)
+            });
+            let color = match emitter.color.color(self.time) {
+                Some(Color { r, g, b }) => vec3(r, g, b),
+                None => vec3(1.0, 1.0, 0.0),
+            };
+            let lifetime = emitter.lifetime;
+            let spawned = spawns
+                .iter()
+                .map(|it| Particle {
+                    color,
+                    // the grid cells only fit particles up to the fixed radius
+                    radius: it.radius.min(MAX_PARTICLE_RADIUS),
+                    position: it.position,
+                    velocity: it.velocity,
+                    expires: lifetime.map_or(NEVER, |lifetime| {
+                        (self.time + lifetime.sample(&mut self.rng) as f64) as f32
+                    }),
+                    _padding: [0.0; 3],
+                })
+                .collect::<Vec<_>>();
+            self.queue.write_buffer(
+                &self.instance_buffer,
+                self.spawned_particles as u64 * mem::size_of::<Particle>() as u64,
+                bytemuck::cast_slice(&spawned),
+            );
+            self.spawned_particles += spawned.len() as u32;
+            self.emitters[i].emitted(spawned.len());
+        }
+    }
+
+    // Push, attract and grab run in the shaders, erasing and painting change the particle
+    // count so they go through a read back instead.
+    fn apply_mouse_tools(&mut self) {
+        let erasing = self.mouse.is_using(Tool::Delete);
+        let painting = self.mouse.is_using(Tool::Paint) && self.spawned_particles < self.capacity;
+        if !erasing && !painting {
+            return;
+        }
+        let tools = self.scene.mouse.clone();
+        let center = self.mouse.position;
+        let mut particles = self.read_particles();
+        // also drops the particles sinks and lifetimes removed since the last compaction
+        particles.retain(|it| it.radius > 0.0);
+        if erasing {
+            particles.retain(|it| it.position.distance(center) >= tools.radius);
+        }
+        if painting {
+            const ATTEMPTS: u32 = 8;
+            for _ in 0..tools.paint_rate {
+                if particles.len() >= self.capacity as usize {
+                    break;
+                }
+                // the grid cells only fit particles up to the fixed radius
+                let radius = self
+                    .rng
+                    .gen_range(tools.paint_radius.0..=tools.paint_radius.1)
+                    .min(MAX_PARTICLE_RADIUS);
+                for _ in 0..ATTEMPTS {
+                    let offset = Vec2::from_angle(self.rng.gen_range(0.0..2.0 * PI))
+                        * (tools.radius * self.rng.gen::<f32>().sqrt());
+                    let position = center + offset;
+                    if particles
+                        .iter()
+                        .all(|it| position.distance(it.position) >= radius + it.radius)
+                    {
+                        particles.push(Particle {
+                            color: vec3(1.0, 1.0, 0.0),
+                            radius,
+                            position,
+                            velocity: Vec2::ZERO,
+                            expires: NEVER,
+                            _padding: [0.0; 3],
+                        });
+                        break;
+                    }
+                }
+            }
+        }
+        let live = particles.len() as u32;
+        // the sort can move whatever is left past the end back in front of removed particles,
+        // so that has to be dead as well
+        particles.resize(
+            particles.len().max(self.spawned_particles as usize),
+            Particle::zeroed(),
+        );
+        self.queue
+            .write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&particles));
+        self.spawned_particles = live;
+    }
+
+    // Sinks and lifetimes zero the radius of the particles they remove, which sorts them
+    // behind the live ones, so every few updates the count is cut back to the live ones.
+    fn compact(&mut self) {
+        let removes = !self.scene.sinks.is_empty()
+            || self.scene.emitters.iter().any(|it| it.lifetime.is_some());
+        if !removes || !self.update_count.is_multiple_of(COMPACT_INTERVAL) {
+            return;
+        }
+        let live = self
+            .read_particles()
+            .iter()
+            .take_while(|it| it.radius > 0.0)
+            .count();
+        self.spawned_particles = live as u32;
+    }
+
     pub fn on_resize(&mut self, size: PhysicalSize<u32>) {
+        let Some(surface) = &self.surface else {
+            return;
+        };
         self.surface_config.width = size.width;
         self.surface_config.height = size.height;
-        self.surface.configure(&self.device, &self.surface_config);
-        self.camera_uniform.on_resize(&self.queue, size, FOV);
+        surface.configure(&self.device, &self.surface_config);
+        self.camera_uniform
+            .on_resize(&self.queue, size, fov(&self.scene.bounds));
     }
 
-    pub fn render(&self, blend: f64, dt: f64) {
-        let surface_texture = self
-            .surface
+    pub fn render(&mut self, blend: f64, dt: f64) {
+        let Some(surface) = &self.surface else {
+            return;
+        };
+        let surface_texture = surface
             .get_current_texture()
             .expect("Failed to acquire next swap chain texture");
 
@@ -548,12 +814,47 @@ impl Simulation {
             render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
             render_pass.set_pipeline(&self.render_pipeline);
             render_pass.draw(0..4, 0..self.spawned_particles as u32);
+            self.mouse_renderer.render(
+                &mut render_pass,
+                &self.queue,
+                &self.square_mesh,
+                self.mouse.pressed,
+                dt,
+            );
         }
 
         self.queue.submit(std::iter::once(encoder.finish()));
         surface_texture.present();
     }
 
+    pub fn read_particles(&self) -> Vec<Particle> {
+        // wgpu has no empty buffer slices
+        if self.spawned_particles == 0 {
+            return vec![];
+        }
+        let size = (self.spawned_particles as usize * mem::size_of::<Particle>()) as u64;
+        let staging_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("ParticleReadbackBuffer"),
+            size: round_buffer_size(size),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(&self.instance_buffer, 0, &staging_buffer, 0, size);
+        self.queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = staging_buffer.slice(..size);
+        slice.map_async(wgpu::MapMode::Read, |result| {
+            result.expect("Failed to map particle buffer")
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        let particles = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+        staging_buffer.unmap();
+        particles
+    }
+
     pub fn on_event(&mut self, event: &Event) {
         match event {
             Event::FileUpdated(_) => {
@@ -564,13 +865,133 @@ impl Simulation {
                     &self.compute_bind_group_layout,
                     &self.surface_config,
                     &self.shader_module,
-                )
+                );
+                self.mouse_renderer
+                    .on_shader_updated(&self.device, self.surface_config.format);
             }
             _ => (),
         }
     }
 }
 
+fn warn_unsupported(scene: &Scene) {
+    let features = [
+        ("walls", !scene.walls.is_empty()),
+        ("obstacles", !scene.obstacles.is_empty()),
+        ("chains", !scene.chains.is_empty()),
+        (
+            "bodies",
+            !scene.soft_bodies.is_empty() || !scene.rigid_bodies.is_empty(),
+        ),
+        (
+            "fluids",
+            scene.materials.iter().any(|it| it.fluid.is_some()),
+        ),
+        (
+            "cohesion",
+            scene
+                .materials
+                .iter()
+                .any(|it| it.cohesion > 0.0 || it.adhesion > 0.0),
+        ),
+        ("interactions", !scene.interactions.is_empty()),
+    ];
+    let unsupported = features
+        .iter()
+        .filter(|(_, used)| *used)
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>();
+    if !unsupported.is_empty() {
+        println!(
+            "Only the cpu backend supports {}, ignoring",
+            unsupported.join(", ")
+        );
+    }
+    let largest_radius = scene
+        .emitters
+        .iter()
+        .map(|it| it.radius.max())
+        .fold(scene.mouse.paint_radius.1, f32::max);
+    if largest_radius > MAX_PARTICLE_RADIUS {
+        println!(
+            "The gpu backend clamps particle radii to {MAX_PARTICLE_RADIUS}, \
+             the scene asks for up to {largest_radius}"
+        );
+    }
+    if scene.sinks.len() > MAX_SINKS {
+        println!("The gpu backend supports at most {MAX_SINKS} sinks, ignoring the rest");
+    }
+    if scene.force_fields.len() >= MAX_FORCE_FIELDS {
+        println!(
+            "The gpu backend applies at most {MAX_FORCE_FIELDS} force fields, \
+             one less while pushing or attracting"
+        );
+    }
+}
+
+fn create_grid_buffers(
+    device: &wgpu::Device,
+    grid: &FixedSizeGrid,
+    capacity: u32,
+) -> (wgpu::Buffer, wgpu::Buffer) {
+    let grid_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("GridBuffer"),
+        // contents: bytemuck::cast_slice(&[0u32; (grid.size.x * grid.size.y) as usize]),
+        usage: wgpu::BufferUsages::STORAGE,
+        size: (mem::size_of::<u32>() as u32 * grid.size.x * grid.size.y) as u64,
+        mapped_at_creation: false,
+    });
+    let sort_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("SortBuffer"),
+        contents: bytemuck::cast_slice(&[Sort {
+            pass_index: 0,
+            sorting_length: capacity,
+            grid_size: grid.size,
+            cell_size: grid.cell_size,
+            origin: grid.origin,
+        }]),
+        usage: wgpu::BufferUsages::UNIFORM,
+    });
+    (grid_buffer, sort_buffer)
+}
+
+fn create_compute_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    instance_buffer: &wgpu::Buffer,
+    simulation_uniform: &SimulationUniform,
+    grid_buffer: &wgpu::Buffer,
+    sort_buffer: &wgpu::Buffer,
+    grid_index_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("ComputeBindGroup"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: instance_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: simulation_uniform.get_binding_resource(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: grid_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: sort_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: grid_index_buffer.as_entire_binding(),
+            },
+        ],
+    })
+}
+
 fn load_shader(device: &wgpu::Device) -> wgpu::ShaderModule {
     println!("Loading shader");
     let text = std::fs::read_to_string(SHADER_FILE).expect("Shader file not found");
@@ -637,8 +1058,9 @@ fn create_pipeline(
         cache: None,
     });
 
-    let [update, sort, clear_grid, fill_grid, calculate_grid_indexes, colorize_grid, collide_grid1, collide_grid2, collide_grid3, collide_grid4, collide_grid5, collide_grid6, collide, finalize] =
+    let [grab, update, sort, clear_grid, fill_grid, calculate_grid_indexes, colorize_grid, collide_grid1, collide_grid2, collide_grid3, collide_grid4, collide_grid5, collide_grid6, collide, finalize] =
         [
+            "grab_entry",
             "update_entry",
             "sort_particles_entry",
             "clear_grid_entry",
@@ -667,6 +1089,7 @@ fn create_pipeline(
     (
         render,
         ComputePipeline {
+            grab,
             update,
             sort,
             clear_grid,
@@ -686,6 +1109,7 @@ fn create_pipeline(
 }
 
 struct ComputePipeline {
+    pub grab: wgpu::ComputePipeline,
     pub update: wgpu::ComputePipeline,
     pub collide: wgpu::ComputePipeline,
     pub clear_grid: wgpu::ComputePipeline,
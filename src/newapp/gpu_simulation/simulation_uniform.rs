@@ -1,15 +1,113 @@
 use std::num::NonZero;
 
 use bytemuck::{Pod, Zeroable};
+use glam::{vec2, Vec2};
 use wgpu::util::DeviceExt;
 use winit::dpi::PhysicalSize;
 
+use crate::newapp::simulation::{
+    force_field::ForceField,
+    mouse::{Mouse, Tool},
+    scene::{self, Scene},
+    sink::Sink,
+};
+
+pub const MAX_FORCE_FIELDS: usize = 8;
+pub const MAX_SINKS: usize = 8;
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, Zeroable, Pod)]
 struct Instance {
     spawned_particles: u32,
     dt: f32,
+    gravity: Vec2,
+    bounds_min: Vec2,
+    bounds_max: Vec2,
     bound_radius: f32,
+    damping: f32,
+    max_speed: f32,
+    // the gpu backend has no per particle materials, walls use the scene's first one
+    restitution: f32,
+    static_friction: f32,
+    dynamic_friction: f32,
+    time: f32,
+    force_field_count: u32,
+    force_fields: [Field; MAX_FORCE_FIELDS],
+    grab_position: Vec2,
+    grab_offset: Vec2,
+    grab_velocity: Vec2,
+    grab_radius: f32,
+    grabbing: u32,
+    sink_count: u32,
+    _padding: [u32; 3],
+    sinks: [SinkShape; MAX_SINKS],
+}
+
+// A ForceField flattened for the shader, see `velocity_change` in compute.wgsl for the kinds.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Zeroable, Pod)]
+struct Field {
+    kind: u32,
+    _padding: u32,
+    // the center, or the acceleration of a uniform field
+    vector: Vec2,
+    parameters: [f32; 4],
+}
+
+impl Field {
+    fn new(field: &ForceField) -> Self {
+        let (kind, vector, parameters) = match *field {
+            ForceField::Uniform { acceleration } => (0, acceleration, [0.0; 4]),
+            ForceField::Radial {
+                center,
+                radius,
+                strength,
+            } => (1, center, [radius, strength, 0.0, 0.0]),
+            ForceField::Vortex {
+                center,
+                radius,
+                strength,
+            } => (2, center, [radius, strength, 0.0, 0.0]),
+            ForceField::Noise {
+                strength,
+                scale,
+                speed,
+            } => (3, Vec2::ZERO, [strength, scale, speed, 0.0]),
+            ForceField::Drag { linear, quadratic } => {
+                (4, Vec2::ZERO, [linear, quadratic, 0.0, 0.0])
+            }
+        };
+        Self {
+            kind,
+            _padding: 0,
+            vector,
+            parameters,
+        }
+    }
+}
+
+// A Sink flattened for the shader, kind 0 is a circle with the center and radius in `bounds`,
+// 1 a box from `bounds.xy` to `bounds.zw`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Zeroable, Pod)]
+struct SinkShape {
+    kind: u32,
+    _padding: [u32; 3],
+    bounds: [f32; 4],
+}
+
+impl SinkShape {
+    fn new(sink: &Sink) -> Self {
+        let (kind, bounds) = match *sink {
+            Sink::Circle { center, radius } => (0, [center.x, center.y, radius, 0.0]),
+            Sink::Box { min, max } => (1, [min.x, min.y, max.x, max.y]),
+        };
+        Self {
+            kind,
+            _padding: [0; 3],
+            bounds,
+        }
+    }
 }
 
 pub struct SimulationUniform {
@@ -48,22 +146,64 @@ impl SimulationUniform {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn update(
         &mut self,
         device: &wgpu::Device,
         encoder: &mut wgpu::CommandEncoder,
         queue: &wgpu::Queue,
         spawned_particles: u32,
-        bound_radius: f32,
+        scene: &Scene,
+        mouse: &Mouse,
+        time: f32,
         dt: f32,
     ) {
+        let bounds = scene.bounds;
+        let bounds_min = vec2(bounds.left, bounds.bottom);
+        let bounds_max = vec2(bounds.right, bounds.top);
+        let material = scene::material(&scene.materials, 0);
+        let mouse_field = mouse.force_field(&scene.mouse);
+        let mut force_fields = [Field::zeroed(); MAX_FORCE_FIELDS];
+        let mut force_field_count = 0;
+        // the mouse goes first so too many scene fields can't crowd it out
+        for (field, it) in force_fields
+            .iter_mut()
+            .zip(mouse_field.iter().chain(&scene.force_fields))
+        {
+            *field = Field::new(it);
+            force_field_count += 1;
+        }
+        let mut sinks = [SinkShape::zeroed(); MAX_SINKS];
+        for (sink, it) in sinks.iter_mut().zip(&scene.sinks) {
+            *sink = SinkShape::new(it);
+        }
+        let grab_offset = mouse.position - mouse.last_position;
         queue.write_buffer(
             &self.buffer,
             0,
             bytemuck::cast_slice(&[Instance {
                 spawned_particles,
                 dt,
-                bound_radius,
+                gravity: scene.gravity,
+                bounds_min,
+                bounds_max,
+                bound_radius: ((bounds_max - bounds_min) / 2.0).min_element(),
+                damping: scene.damping,
+                max_speed: scene.max_speed,
+                restitution: material.restitution,
+                static_friction: material.static_friction,
+                dynamic_friction: material.dynamic_friction,
+                time,
+                force_field_count,
+                force_fields,
+                grab_position: mouse.last_position,
+                grab_offset,
+                grab_velocity: grab_offset / (dt * scene.substeps.max(1) as f32),
+                grab_radius: scene.mouse.radius,
+                grabbing: mouse.is_using(Tool::Grab) as u32,
+                sink_count: scene.sinks.len().min(MAX_SINKS) as u32,
+                _padding: [0; 3],
+                sinks,
             }]),
         );
         // self.staging_buffer.slice(..).map
@@ -0,0 +1,142 @@
+use std::time::Instant;
+
+use super::{
+    cli::{Args, Backend},
+    export::{self, Exporter, Field, Record},
+    gpu_simulation::Simulation as GpuSimulation,
+    profiler::{self, Profiler},
+    simulation::{recording::Session, Simulation},
+};
+
+const FIXED_DT: f32 = 0.016666;
+const DEFAULT_FRAMES: u32 = 600;
+const OUTPUT_FIELDS: [Field; 3] = [Field::Position, Field::Velocity, Field::Radius];
+
+trait Stepper {
+    /// Returns false once there is nothing left to run.
+    fn step(&mut self, profiler: &mut Profiler) -> bool;
+    fn records(&mut self) -> Vec<Record>;
+}
+
+struct CpuStepper {
+    simulation: Simulation,
+    session: Session,
+}
+
+impl Stepper for CpuStepper {
+    fn step(&mut self, profiler: &mut Profiler) -> bool {
+        self.session
+            .step(&mut self.simulation, FIXED_DT, vec![], profiler)
+    }
+
+    fn records(&mut self) -> Vec<Record> {
+        export::cpu_records(&self.simulation)
+    }
+}
+
+impl Stepper for GpuSimulation {
+    fn step(&mut self, profiler: &mut Profiler) -> bool {
+        self.update(FIXED_DT, profiler);
+        true
+    }
+
+    fn records(&mut self) -> Vec<Record> {
+        export::gpu_records(&self.read_particles())
+    }
+}
+
+pub fn run(args: Args) {
+    let scene = args.load_scene();
+    let frames = args
+        .frames
+        .or(args.replay.as_ref().map(|_| u32::MAX))
+        .unwrap_or(DEFAULT_FRAMES);
+    let mut exporter = args.exporter();
+    let started = Instant::now();
+    let mut diverged = false;
+
+    let (frames, records) = match args.backend {
+        Backend::Cpu => {
+            let (simulation, session) = args.cpu_session(scene);
+            let mut stepper = CpuStepper {
+                simulation,
+                session,
+            };
+            let frames = run_frames(&mut stepper, frames, exporter.as_mut());
+            match (&stepper.session, &args.record) {
+                (Session::Recording(recording), Some(path)) => {
+                    recording.save(path).expect("Failed to save recording");
+                    println!(
+                        "Recording of {} steps saved to {path:?}",
+                        recording.steps.len()
+                    );
+                }
+                (Session::Replay(replay), _) => diverged = replay.first_divergence().is_some(),
+                _ => (),
+            }
+            if let Some(path) = &args.save_snapshot {
+                stepper
+                    .simulation
+                    .save_snapshot(path)
+                    .expect("Failed to save snapshot");
+                println!("Snapshot saved to {path:?}");
+            }
+            (frames, stepper.records())
+        }
+        Backend::Gpu => {
+            if args.record.is_some() || args.replay.is_some() {
+                println!("Recording is only supported by the cpu backend, ignoring");
+            }
+            let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+            let mut simulation = pollster::block_on(GpuSimulation::new_headless(&instance, scene));
+            let frames = run_frames(&mut simulation, frames, exporter.as_mut());
+            (frames, simulation.records())
+        }
+    };
+
+    println!(
+        "Finished {frames} frames with {} particles in {:.3}s",
+        records.len(),
+        started.elapsed().as_secs_f64()
+    );
+    if let (Some(exporter), Some(path)) = (exporter, &args.export) {
+        exporter.finish().expect("Failed to finish export");
+        println!("Trajectory exported to {path:?}");
+    }
+    if let Some(path) = &args.output {
+        export::write_csv(path, &OUTPUT_FIELDS, &records).expect("Failed to write results");
+        println!("Results written to {path:?}");
+    }
+    if diverged {
+        std::process::exit(1);
+    }
+}
+
+// Frame n is the state after n steps, so the initial state is exported as frame 0.
+fn run_frames(stepper: &mut dyn Stepper, frames: u32, mut exporter: Option<&mut Exporter>) -> u32 {
+    let mut profiler = Profiler::new();
+    let mut last_displayed_time = Instant::now();
+    let mut export = |stepper: &mut dyn Stepper, frame: u32| {
+        if let Some(exporter) = exporter.as_mut().filter(|it| it.wants(frame)) {
+            exporter
+                .write_frame(frame, frame as f32 * FIXED_DT, &stepper.records())
+                .expect("Failed to export frame");
+        }
+    };
+
+    export(stepper, 0);
+    for frame in 0..frames {
+        profiler.start(profiler::Kind::FixedUpdate);
+        let running = stepper.step(&mut profiler);
+        profiler.end(profiler::Kind::FixedUpdate);
+        if !running {
+            return frame;
+        }
+        export(stepper, frame + 1);
+        if last_displayed_time.elapsed().as_secs_f64() >= 1.0 {
+            profiler.display();
+            last_displayed_time = Instant::now();
+        }
+    }
+    frames
+}
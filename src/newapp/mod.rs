@@ -1,6 +1,9 @@
 mod application;
 pub mod application_handler;
+pub mod cli;
+mod export;
 mod gpu_simulation;
+pub mod headless;
 mod profiler;
 mod rendering;
 mod simulation;
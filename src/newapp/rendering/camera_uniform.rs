@@ -1,6 +1,9 @@
 use bytemuck::{Pod, Zeroable};
+use glam::{vec2, Vec2};
 use wgpu::util::DeviceExt;
-use winit::dpi::PhysicalSize;
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+
+use crate::newapp::simulation::box_constraint::BoxConstraint;
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, Zeroable, Pod)]
@@ -42,3 +45,16 @@ impl CameraUniform {
         self.buffer.as_entire_binding()
     }
 }
+
+pub fn fov(bounds: &BoxConstraint) -> f32 {
+    (bounds.right - bounds.left).max(bounds.top - bounds.bottom)
+}
+
+/// Inverse of `to_camera_pos` in the shaders, `fov` spans the shorter side of the screen.
+pub fn screen_to_world(position: PhysicalPosition<f64>, size: PhysicalSize<u32>, fov: f32) -> Vec2 {
+    let scale = fov / size.width.min(size.height).max(1) as f32;
+    vec2(
+        (position.x as f32 - size.width as f32 / 2.0) * scale,
+        (size.height as f32 / 2.0 - position.y as f32) * scale,
+    )
+}
@@ -0,0 +1,151 @@
+use std::mem;
+
+use bytemuck::{Pod, Zeroable};
+
+use crate::newapp::simulation::Simulation;
+
+use super::{square_mesh::SquareMesh, wgpu_utils::round_buffer_size, RenderingContext};
+
+pub const SHADER_FILE: &str = "shaders/joint.wgsl";
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct Instance {
+    pub start: glam::Vec2,
+    pub end: glam::Vec2,
+}
+
+impl Instance {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRS: [wgpu::VertexAttribute; 2] =
+            wgpu::vertex_attr_array![1 => Float32x2, 2 => Float32x2];
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<Self>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRS,
+        }
+    }
+}
+
+pub struct JointRenderer {
+    shader_module: wgpu::ShaderModule,
+    pipeline: wgpu::RenderPipeline,
+    instance_buffer: wgpu::Buffer,
+    capacity: u64,
+}
+
+const INITIAL_CAPACITY: u64 = 1024;
+
+fn create_instance_buffer(device: &wgpu::Device, capacity: u64) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("JointInstanceBuffer"),
+        size: round_buffer_size(capacity * mem::size_of::<Instance>() as wgpu::BufferAddress),
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+impl JointRenderer {
+    pub fn new(context: &RenderingContext) -> Self {
+        let shader_module = load_shader(&context.device);
+        let pipeline = create_pipeline(context, &shader_module);
+        Self {
+            shader_module,
+            pipeline,
+            instance_buffer: create_instance_buffer(&context.device, INITIAL_CAPACITY),
+            capacity: INITIAL_CAPACITY,
+        }
+    }
+
+    pub fn on_shader_updated(&mut self, context: &RenderingContext) {
+        self.shader_module = load_shader(&context.device);
+        self.pipeline = create_pipeline(context, &self.shader_module);
+    }
+
+    pub fn render(
+        &mut self,
+        render_pass: &mut wgpu::RenderPass,
+        context: &RenderingContext,
+        square_mesh: &SquareMesh,
+        simulation: &Simulation,
+    ) {
+        let joints = simulation
+            .joint_ends()
+            .map(|(start, end)| Instance { start, end })
+            .collect::<Vec<_>>();
+        if joints.is_empty() {
+            return;
+        }
+        if joints.len() as u64 > self.capacity {
+            self.capacity = (joints.len() as u64).next_power_of_two();
+            self.instance_buffer = create_instance_buffer(&context.device, self.capacity);
+        }
+        context
+            .queue
+            .write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&joints));
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_vertex_buffer(0, square_mesh.vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        render_pass.draw(0..4, 0..joints.len() as u32);
+    }
+}
+
+fn load_shader(device: &wgpu::Device) -> wgpu::ShaderModule {
+    let text = std::fs::read_to_string(SHADER_FILE).expect("Shader file not found");
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("JointShader"),
+        source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(&text)),
+    })
+}
+
+fn create_pipeline(
+    context: &RenderingContext,
+    shader: &wgpu::ShaderModule,
+) -> wgpu::RenderPipeline {
+    let pipeline_layout = context
+        .device
+        .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("JointPipelineLayout"),
+            bind_group_layouts: &[&context.main_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+    context
+        .device
+        .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("JointPipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                buffers: &[SquareMesh::desc(), Instance::desc()],
+                entry_point: Some("vs_joint"),
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: Some("fs_joint"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: context.surface_config.format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        })
+}
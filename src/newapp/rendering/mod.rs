@@ -1,12 +1,19 @@
 pub mod camera_uniform;
+mod joints;
+pub mod mouse;
 mod simulation;
 pub mod square_mesh;
 pub mod wgpu_utils;
 
 use camera_uniform::CameraUniform;
+use joints::JointRenderer;
+use mouse::MouseRenderer;
 use simulation::SimulationRenderer;
 use square_mesh::SquareMesh;
-use winit::{dpi::PhysicalSize, event_loop::EventLoopProxy};
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    event_loop::EventLoopProxy,
+};
 
 use super::{
     application_handler::Event, gpu_simulation::Simulation as GpuSimulation,
@@ -27,6 +34,8 @@ pub struct Renderer {
     shader_module: wgpu::ShaderModule,
     square_mesh: SquareMesh,
     simulation_renderer: SimulationRenderer,
+    joint_renderer: JointRenderer,
+    mouse_renderer: MouseRenderer,
     camera_uniform: CameraUniform,
 }
 
@@ -41,6 +50,8 @@ impl Renderer {
         fov: f32,
     ) -> Self {
         watch_file::init(proxy, SHADER_FILE);
+        watch_file::init(proxy, joints::SHADER_FILE);
+        watch_file::init(proxy, mouse::SHADER_FILE);
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
                 power_preference: wgpu::PowerPreference::HighPerformance,
@@ -122,6 +133,12 @@ impl Renderer {
         let square_mesh = SquareMesh::new(&context.device);
 
         let simulation_renderer = SimulationRenderer::new(&context, &shader_module);
+        let joint_renderer = JointRenderer::new(&context);
+        let mouse_renderer = MouseRenderer::new(
+            &context.device,
+            context.surface_config.format,
+            &camera_uniform,
+        );
 
         Self {
             context,
@@ -129,6 +146,8 @@ impl Renderer {
             shader_module,
             square_mesh,
             simulation_renderer,
+            joint_renderer,
+            mouse_renderer,
             camera_uniform,
         }
     }
@@ -143,6 +162,12 @@ impl Renderer {
     pub fn on_event(&mut self, event: &Event) {
         match event {
             Event::FileUpdated(SHADER_FILE) => self.load_shader(),
+            Event::FileUpdated(joints::SHADER_FILE) => {
+                self.joint_renderer.on_shader_updated(&self.context)
+            }
+            Event::FileUpdated(mouse::SHADER_FILE) => self
+                .mouse_renderer
+                .on_shader_updated(&self.context.device, self.context.surface_config.format),
             _ => (),
         }
     }
@@ -153,6 +178,10 @@ impl Renderer {
             .on_shader_updated(&self.context, &self.shader_module);
     }
 
+    pub fn on_cursor_moved(&mut self, position: PhysicalPosition<f64>) {
+        self.mouse_renderer.on_cursor_moved(position);
+    }
+
     pub fn on_resize(&mut self, size: PhysicalSize<u32>, fov: f32) {
         self.context.surface_config.width = size.width;
         self.context.surface_config.height = size.height;
@@ -163,7 +192,7 @@ impl Renderer {
     }
 
     pub fn render_gpu(&mut self, simulation: &mut GpuSimulation, _: f64, _: f64) {}
-    pub fn render(&mut self, simulation: &mut Simulation, _: f64, _: f64) {
+    pub fn render(&mut self, simulation: &mut Simulation, _: f64, dt: f64) {
         let surface_texture = self
             .surface
             .get_current_texture()
@@ -202,10 +231,23 @@ impl Renderer {
 
             self.simulation_renderer.render(
                 &mut render_pass,
-                &self.context.queue,
+                &self.context,
+                &self.square_mesh,
+                simulation,
+            );
+            self.joint_renderer.render(
+                &mut render_pass,
+                &self.context,
                 &self.square_mesh,
                 simulation,
             );
+            self.mouse_renderer.render(
+                &mut render_pass,
+                &self.context.queue,
+                &self.square_mesh,
+                simulation.mouse().pressed,
+                dt,
+            );
         }
 
         self.context.queue.submit(std::iter::once(encoder.finish()));
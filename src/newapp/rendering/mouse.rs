@@ -0,0 +1,175 @@
+use bytemuck::{Pod, Zeroable};
+use glam::{vec2, Vec2};
+use winit::dpi::PhysicalPosition;
+
+use super::{camera_uniform::CameraUniform, square_mesh::SquareMesh};
+
+pub const SHADER_FILE: &str = "shaders/mouse.wgsl";
+// seconds the cursor takes to grow or shrink on a click
+const ANIMATION_TIME: f64 = 0.15;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Pod, Zeroable)]
+struct MouseState {
+    // in pixels from the top left corner
+    position: Vec2,
+    animation_progress: f32,
+    is_clicked: u32,
+}
+
+/// Draws the cursor, grows while the mouse button is held.
+pub struct MouseRenderer {
+    shader_module: wgpu::ShaderModule,
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    buffer: wgpu::Buffer,
+    state: MouseState,
+}
+
+impl MouseRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        camera_uniform: &CameraUniform,
+    ) -> Self {
+        let state = MouseState {
+            position: vec2(-100.0, -100.0),
+            animation_progress: 1.0,
+            is_clicked: 0,
+        };
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("MouseUniform"),
+            size: std::mem::size_of::<MouseState>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let uniform_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("MouseBindGroupLayout"),
+            entries: &[uniform_entry(0), uniform_entry(1)],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("MouseBindGroup"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: camera_uniform.get_binding_resource(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: buffer.as_entire_binding(),
+                },
+            ],
+        });
+        let shader_module = load_shader(device);
+        let pipeline = create_pipeline(device, format, &bind_group_layout, &shader_module);
+        Self {
+            shader_module,
+            pipeline,
+            bind_group_layout,
+            bind_group,
+            buffer,
+            state,
+        }
+    }
+
+    pub fn on_shader_updated(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat) {
+        self.shader_module = load_shader(device);
+        self.pipeline =
+            create_pipeline(device, format, &self.bind_group_layout, &self.shader_module);
+    }
+
+    pub fn on_cursor_moved(&mut self, position: PhysicalPosition<f64>) {
+        self.state.position = vec2(position.x as f32, position.y as f32);
+    }
+
+    pub fn render(
+        &mut self,
+        render_pass: &mut wgpu::RenderPass,
+        queue: &wgpu::Queue,
+        square_mesh: &SquareMesh,
+        pressed: bool,
+        dt: f64,
+    ) {
+        if self.state.is_clicked != pressed as u32 {
+            self.state.is_clicked = pressed as u32;
+            self.state.animation_progress = 0.0;
+        }
+        self.state.animation_progress =
+            (self.state.animation_progress + (dt / ANIMATION_TIME) as f32).min(1.0);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.state]));
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, square_mesh.vertex_buffer.slice(..));
+        render_pass.draw(0..4, 0..1);
+    }
+}
+
+fn load_shader(device: &wgpu::Device) -> wgpu::ShaderModule {
+    let text = std::fs::read_to_string(SHADER_FILE).expect("Shader file not found");
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("MouseShader"),
+        source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(&text)),
+    })
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    shader: &wgpu::ShaderModule,
+) -> wgpu::RenderPipeline {
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("MousePipelineLayout"),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("MousePipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            buffers: &[SquareMesh::desc()],
+            entry_point: Some("vs_mouse"),
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: Some("fs_mouse"),
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: Default::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleStrip,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+        cache: None,
+    })
+}
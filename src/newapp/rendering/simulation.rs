@@ -48,6 +48,7 @@ pub struct SimulationRenderer {
     pipeline: wgpu::RenderPipeline,
     instance_buffer: wgpu::Buffer,
     // color_instance_buffer: wgpu::Buffer,
+    capacity: u64,
 }
 
 const MAX_PARTICLES: u64 = 181000;
@@ -57,8 +58,17 @@ const MAX_PARTICLES: u64 = 181000;
 //         (MAX_PARTICLES as usize * mem::size_of::<ColorInstance>()) as wgpu::BufferAddress,
 //     )
 // }
-fn get_particle_buffer_size() -> wgpu::BufferAddress {
-    round_buffer_size((MAX_PARTICLES as usize * mem::size_of::<Instance>()) as wgpu::BufferAddress)
+fn get_particle_buffer_size(capacity: u64) -> wgpu::BufferAddress {
+    round_buffer_size((capacity as usize * mem::size_of::<Instance>()) as wgpu::BufferAddress)
+}
+
+fn create_instance_buffer(device: &wgpu::Device, capacity: u64) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("SimulationInstanceBuffer"),
+        size: get_particle_buffer_size(capacity),
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
 }
 
 impl SimulationRenderer {
@@ -69,17 +79,13 @@ impl SimulationRenderer {
         //     usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
         //     mapped_at_creation: false,
         // });
-        let instance_buffer = context.device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("SimulationInstanceBuffer"),
-            size: get_particle_buffer_size(),
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
+        let instance_buffer = create_instance_buffer(&context.device, MAX_PARTICLES);
         let pipeline = create_pipeline(context, shader_module);
         Self {
             pipeline,
             instance_buffer,
             // color_instance_buffer,
+            capacity: MAX_PARTICLES,
         }
     }
 
@@ -92,9 +98,9 @@ impl SimulationRenderer {
     }
 
     pub fn render(
-        &self,
+        &mut self,
         render_pass: &mut wgpu::RenderPass,
-        queue: &wgpu::Queue,
+        context: &RenderingContext,
         square_mesh: &SquareMesh,
         simulation: &mut Simulation,
     ) {
@@ -120,7 +126,13 @@ impl SimulationRenderer {
         //         bytemuck::cast_slice(&colors),
         //     )
         // }
-        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&particles));
+        if particles.len() as u64 > self.capacity {
+            self.capacity = (particles.len() as u64).next_power_of_two();
+            self.instance_buffer = create_instance_buffer(&context.device, self.capacity);
+        }
+        context
+            .queue
+            .write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&particles));
         render_pass.set_pipeline(&self.pipeline);
         render_pass.set_vertex_buffer(0, square_mesh.vertex_buffer.slice(..));
         render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
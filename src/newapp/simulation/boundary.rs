@@ -0,0 +1,222 @@
+use glam::{vec2, UVec2, Vec2};
+use serde::{Deserialize, Serialize};
+
+use super::{friction, scene::Material, Particle};
+
+/// A solid the particles collide with.
+pub trait Boundary {
+    /// Signed distance from `point` to the surface, negative inside the solid,
+    /// and the outward surface normal closest to `point`.
+    fn distance(&self, point: Vec2) -> (f32, Vec2);
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Circle {
+    pub center: Vec2,
+    pub radius: f32,
+}
+
+/// A convex polygon, the winding doesn't matter.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Polygon {
+    pub points: Vec<Vec2>,
+}
+
+/// All points within `radius` of the segment `start..end`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Capsule {
+    pub start: Vec2,
+    pub end: Vec2,
+    pub radius: f32,
+}
+
+/// Signed distances sampled on a grid of `size` points `spacing` apart starting at `origin`,
+/// row by row from the bottom. Points outside the grid use the closest sample.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DistanceField {
+    pub origin: Vec2,
+    pub spacing: f32,
+    pub size: UVec2,
+    pub values: Vec<f32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Shape {
+    Circle(Circle),
+    Polygon(Polygon),
+    Capsule(Capsule),
+    DistanceField(DistanceField),
+}
+
+/// A static shape inside the scene bounds, particles are kept outside of it
+/// unless `contains` is set, which makes it a container like the bounds.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Wall {
+    pub shape: Shape,
+    #[serde(default)]
+    pub contains: bool,
+}
+
+impl Boundary for Circle {
+    fn distance(&self, point: Vec2) -> (f32, Vec2) {
+        let offset = point - self.center;
+        (
+            offset.length() - self.radius,
+            offset.normalize_or(vec2(0.0, 1.0)),
+        )
+    }
+}
+
+impl Boundary for Polygon {
+    fn distance(&self, point: Vec2) -> (f32, Vec2) {
+        let mut closest = (f32::MAX, Vec2::ZERO);
+        let (mut left, mut right) = (false, false);
+        for (i, &start) in self.points.iter().enumerate() {
+            let end = self.points[(i + 1) % self.points.len()];
+            let edge = end - start;
+            let side = edge.perp_dot(point - start);
+            left |= side > 0.0;
+            right |= side < 0.0;
+            let offset = point - closest_on_segment(start, end, point);
+            let distance = offset.length();
+            if distance < closest.0 {
+                // on the surface the offset has no direction, the edge normal has to do
+                let normal = edge.perp().normalize_or_zero() * side.signum();
+                closest = (distance, offset.normalize_or(normal));
+            }
+        }
+        let (distance, direction) = closest;
+        // inside a convex polygon every edge has the point on the same side
+        if left != right {
+            (-distance, -direction)
+        } else {
+            (distance, direction)
+        }
+    }
+}
+
+impl Boundary for Capsule {
+    fn distance(&self, point: Vec2) -> (f32, Vec2) {
+        let offset = point - closest_on_segment(self.start, self.end, point);
+        (
+            offset.length() - self.radius,
+            offset.normalize_or((self.end - self.start).perp().normalize_or(vec2(0.0, 1.0))),
+        )
+    }
+}
+
+impl Boundary for DistanceField {
+    fn distance(&self, point: Vec2) -> (f32, Vec2) {
+        let h = self.spacing * 0.5;
+        let gradient = vec2(
+            self.sample(point + vec2(h, 0.0)) - self.sample(point - vec2(h, 0.0)),
+            self.sample(point + vec2(0.0, h)) - self.sample(point - vec2(0.0, h)),
+        );
+        (self.sample(point), gradient.normalize_or(vec2(0.0, 1.0)))
+    }
+}
+
+impl DistanceField {
+    // bilinear
+    fn sample(&self, point: Vec2) -> f32 {
+        if self.size.x == 0 || self.size.y == 0 {
+            return f32::MAX;
+        }
+        let max = (self.size - 1).as_vec2();
+        let position = ((point - self.origin) / self.spacing).clamp(Vec2::ZERO, max);
+        let cell = position.floor().min(max - 1.0).max(Vec2::ZERO);
+        let t = position - cell;
+        let value = |x: f32, y: f32| {
+            let x = (x as u32).min(self.size.x - 1);
+            let y = (y as u32).min(self.size.y - 1);
+            self.values
+                .get((x + y * self.size.x) as usize)
+                .copied()
+                .unwrap_or(f32::MAX)
+        };
+        let bottom = value(cell.x, cell.y) * (1.0 - t.x) + value(cell.x + 1.0, cell.y) * t.x;
+        let top =
+            value(cell.x, cell.y + 1.0) * (1.0 - t.x) + value(cell.x + 1.0, cell.y + 1.0) * t.x;
+        bottom * (1.0 - t.y) + top * t.y
+    }
+}
+
+impl Boundary for Shape {
+    fn distance(&self, point: Vec2) -> (f32, Vec2) {
+        match self {
+            Shape::Circle(it) => it.distance(point),
+            Shape::Polygon(it) => it.distance(point),
+            Shape::Capsule(it) => it.distance(point),
+            Shape::DistanceField(it) => it.distance(point),
+        }
+    }
+}
+
+impl Wall {
+    /// Same response as `BoxConstraint::apply`, along the normal of the closest surface.
+    pub fn apply(
+        &self,
+        particle: &mut Particle,
+        previous_position: &mut Vec2,
+        material: &Material,
+        dt: f32,
+        threshold: f32,
+    ) {
+        let (distance, normal) = self.shape.distance(particle.position);
+        // points away from the solid side
+        let (penetration, normal) = if self.contains {
+            (distance + particle.radius, -normal)
+        } else {
+            (particle.radius - distance, normal)
+        };
+        collide(
+            particle,
+            previous_position,
+            (penetration, normal),
+            Vec2::ZERO,
+            material,
+            dt,
+            threshold,
+        );
+    }
+}
+
+/// Pushes the particle `penetration` along the contact `normal`, then applies friction and
+/// restitution relative to a surface moving with `surface_velocity`. Adhesion first pulls
+/// particles within range against the surface, so they also get friction on walls they hang on.
+pub(super) fn collide(
+    particle: &mut Particle,
+    previous_position: &mut Vec2,
+    (mut penetration, normal): (f32, Vec2),
+    surface_velocity: Vec2,
+    material: &Material,
+    dt: f32,
+    threshold: f32,
+) {
+    let velocity = (particle.position - *previous_position) / dt - surface_velocity;
+    if material.adhesion > 0.0 && penetration > -material.cohesion_range {
+        let pull = material.adhesion_pull(particle.radius, dt);
+        particle.position -= normal * pull;
+        penetration += pull;
+    }
+    if penetration <= 0.0 {
+        return;
+    }
+    particle.position += normal * penetration;
+
+    let displacement = particle.position - *previous_position - surface_velocity * dt;
+    let slip = displacement - normal * displacement.dot(normal);
+    particle.position += friction(slip, penetration, material, material);
+
+    let approach = velocity.dot(normal);
+    if approach < -threshold {
+        let bounce = displacement.dot(normal) + approach * material.restitution * dt;
+        *previous_position += normal * bounce;
+    }
+}
+
+pub(super) fn closest_on_segment(start: Vec2, end: Vec2, point: Vec2) -> Vec2 {
+    let edge = end - start;
+    let t = (point - start).dot(edge) / edge.length_squared().max(f32::MIN_POSITIVE);
+    start + edge * t.clamp(0.0, 1.0)
+}
@@ -1,6 +1,9 @@
-use super::Particle;
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy)]
+use super::{friction, scene::Material, Particle};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 pub struct BoxConstraint {
     pub top: f32,
     pub bottom: f32,
@@ -9,7 +12,20 @@ pub struct BoxConstraint {
 }
 
 impl BoxConstraint {
-    pub fn apply(&self, particle: &mut Particle, _dt: f32) {
+    /// Clamps the particle into the box. Friction with the wall removes part of the tangential
+    /// motion since `previous_position`, the bounce moves `previous_position` so that the velocity
+    /// derived from it at the end of the substep points away from the wall. Impacts slower than
+    /// `threshold` don't bounce, which keeps resting particles from jittering. Adhesion pulls
+    /// particles close to a side against it first.
+    pub fn apply(
+        &self,
+        particle: &mut Particle,
+        previous_position: &mut Vec2,
+        material: &Material,
+        dt: f32,
+        threshold: f32,
+    ) {
+        let velocity = (particle.position - *previous_position) / dt;
         let r = particle.radius;
         let top = self.top - r;
         let bottom = self.bottom + r;
@@ -17,18 +33,56 @@ impl BoxConstraint {
         let right = self.right - r;
         let p = &mut particle.position;
 
+        if material.adhesion > 0.0 {
+            let pull = material.adhesion_pull(r, dt);
+            let range = material.cohesion_range;
+            if p.y - bottom < range {
+                p.y -= pull;
+            }
+            if top - p.y < range {
+                p.y += pull;
+            }
+            if p.x - left < range {
+                p.x -= pull;
+            }
+            if right - p.x < range {
+                p.x += pull;
+            }
+        }
+
+        let mut penetration = Vec2::ZERO;
         if p.y < bottom {
+            penetration.y = bottom - p.y;
             p.y = bottom
         }
         if p.y > top {
+            penetration.y = p.y - top;
             p.y = top
         }
         if p.x < left {
+            penetration.x = left - p.x;
             p.x = left
         }
         if p.x > right {
+            penetration.x = p.x - right;
             p.x = right
         }
+
+        let displacement = *p - *previous_position;
+        if penetration.y > 0.0 {
+            let slip = Vec2::new(displacement.x, 0.0);
+            p.x += friction(slip, penetration.y, material, material).x;
+            if velocity.y.abs() > threshold {
+                previous_position.y = p.y + velocity.y * material.restitution * dt;
+            }
+        }
+        if penetration.x > 0.0 {
+            let slip = Vec2::new(0.0, displacement.y);
+            p.y += friction(slip, penetration.x, material, material).y;
+            if velocity.x.abs() > threshold {
+                previous_position.x = p.x + velocity.x * material.restitution * dt;
+            }
+        }
     }
 
     pub fn around_center(radius: f32) -> Self {
@@ -0,0 +1,155 @@
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    scene::{self, Material},
+    Particle,
+};
+
+/// Particles pulled towards the best rigid fit of their rest shape, shape matching
+/// as in Müller et al. 2005 restricted to rotations.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Cluster {
+    /// Initial ids of the particles
+    members: Vec<usize>,
+    /// Offsets from the center of mass in the rest shape
+    rest: Vec<Vec2>,
+    stiffness: f32,
+}
+
+impl Cluster {
+    /// Takes the current positions of `particles` as the rest shape.
+    pub fn new(particles: &[Particle], materials: &[Material], stiffness: f32) -> Self {
+        let center = center_of_mass(
+            particles
+                .iter()
+                .map(|it| (it.position, mass(it, materials))),
+        );
+        Self {
+            members: particles.iter().map(|it| it.initial_id).collect(),
+            rest: particles.iter().map(|it| it.position - center).collect(),
+            stiffness: stiffness.clamp(0.0, 1.0),
+        }
+    }
+
+    /// `indexes` maps initial ids to indexes into `particles`. The stiffness is spread over
+    /// the `iterations` of a substep so it doesn't depend on their number.
+    pub(super) fn solve(
+        &self,
+        particles: &mut [Particle],
+        indexes: &[usize],
+        materials: &[Material],
+        iterations: u32,
+    ) {
+        let stiffness = 1.0 - (1.0 - self.stiffness).powf(1.0 / iterations as f32);
+        let center = center_of_mass(self.members.iter().map(|&id| {
+            let particle = &particles[indexes[id]];
+            (particle.position, mass(particle, materials))
+        }));
+        // the rotation maximizing sum(m * dot(p - c, R q)) is the angle of this vector
+        let mut fit = Vec2::ZERO;
+        for (&id, &rest) in self.members.iter().zip(&self.rest) {
+            let particle = &particles[indexes[id]];
+            let offset = (particle.position - center) * mass(particle, materials);
+            fit += Vec2::new(rest.dot(offset), rest.perp_dot(offset));
+        }
+        let rotation = fit.normalize_or(Vec2::X);
+        let correction =
+            |particle: &Particle, rest: Vec2| center + rotation.rotate(rest) - particle.position;
+        // the rounded rest offsets don't quite average to zero, which would push the body a
+        // little every time, the corrections are small enough to sum up exactly
+        let drift = center_of_mass(self.members.iter().zip(&self.rest).map(|(&id, &rest)| {
+            let particle = &particles[indexes[id]];
+            (correction(particle, rest), mass(particle, materials))
+        }));
+        for (&id, &rest) in self.members.iter().zip(&self.rest) {
+            let particle = &mut particles[indexes[id]];
+            particle.position += (correction(particle, rest) - drift) * stiffness;
+        }
+    }
+
+    /// Drops the members matching `removed` and re-centres the rest shape on the ones left,
+    /// `indexes` must already be up to date for them.
+    pub(super) fn remove_members(
+        &mut self,
+        removed: impl Fn(usize) -> bool,
+        particles: &[Particle],
+        indexes: &[usize],
+        materials: &[Material],
+    ) {
+        let mut kept = 0;
+        for i in 0..self.members.len() {
+            if !removed(self.members[i]) {
+                self.members.swap(kept, i);
+                self.rest.swap(kept, i);
+                kept += 1;
+            }
+        }
+        if kept == self.members.len() {
+            return;
+        }
+        self.members.truncate(kept);
+        self.rest.truncate(kept);
+        let center = center_of_mass(
+            self.members
+                .iter()
+                .zip(&self.rest)
+                .map(|(&id, &rest)| (rest, mass(&particles[indexes[id]], materials))),
+        );
+        for rest in &mut self.rest {
+            *rest -= center;
+        }
+    }
+
+    /// Clusters of a single particle have nothing to hold together.
+    pub(super) fn is_empty(&self) -> bool {
+        self.members.len() < 2
+    }
+
+    /// Rigid clusters move as a single body, their particles are never deformed.
+    pub(super) fn is_rigid(&self) -> bool {
+        self.stiffness >= 1.0
+    }
+
+    /// Replaces the velocities of the particles by the linear and angular velocity
+    /// of the body that preserve their momentum.
+    pub(super) fn apply_rigid_motion(
+        &self,
+        particles: &mut [Particle],
+        indexes: &[usize],
+        materials: &[Material],
+    ) {
+        let members = || self.members.iter().map(|&id| &particles[indexes[id]]);
+        let center = center_of_mass(members().map(|it| (it.position, mass(it, materials))));
+        let velocity = center_of_mass(members().map(|it| (it.velocity, mass(it, materials))));
+        let (momentum, inertia) = members().fold((0.0, 0.0), |(momentum, inertia), it| {
+            let offset = it.position - center;
+            let mass = mass(it, materials);
+            (
+                momentum + offset.perp_dot(it.velocity - velocity) * mass,
+                inertia + offset.length_squared() * mass,
+            )
+        });
+        let angular_velocity = momentum / inertia.max(f32::MIN_POSITIVE);
+        for &id in &self.members {
+            let particle = &mut particles[indexes[id]];
+            particle.velocity = velocity + (particle.position - center).perp() * angular_velocity;
+        }
+    }
+}
+
+fn mass(particle: &Particle, materials: &[Material]) -> f32 {
+    1.0 / scene::material(materials, particle.material).inverse_mass(particle.radius)
+}
+
+// Summed relative to the first point, moves within a substep are tiny compared to the
+// positions and would get lost in the rounding of a large sum.
+fn center_of_mass(mut points: impl Iterator<Item = (Vec2, f32)>) -> Vec2 {
+    let Some((origin, mass)) = points.next() else {
+        return Vec2::ZERO;
+    };
+    let (sum, total) = points.fold((Vec2::ZERO, mass), |(sum, total), (position, mass)| {
+        (sum + (position - origin) * mass, total + mass)
+    });
+    origin + sum / total.max(f32::MIN_POSITIVE)
+}
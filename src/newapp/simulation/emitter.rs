@@ -0,0 +1,270 @@
+use std::f32::consts::PI;
+
+use glam::{vec2, Vec2};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use super::Color;
+
+/// Fires a burst of `width` particles `rate` times a second from `start` until `stop`,
+/// or until it has spawned `budget` particles.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Emitter {
+    pub shape: Shape,
+    pub position: Vec2,
+    /// Mean velocity of the particles, lines and fans are laid out across it
+    pub velocity: Vec2,
+    pub width: u32,
+    pub spacing: f32,
+    /// Bursts per second, a burst per update at most
+    pub rate: f32,
+    /// Seconds of simulated time
+    pub start: f32,
+    pub stop: Option<f32>,
+    pub budget: Option<usize>,
+    /// Swings the direction up to `sweep` radians either way and back every `sweep_period` seconds
+    pub sweep: f32,
+    pub sweep_period: f32,
+    /// Random change of the speed as a fraction of it
+    pub speed_jitter: f32,
+    /// Random change of the direction in radians
+    pub angle_jitter: f32,
+    pub radius: Distribution,
+    /// Seconds until the particles are removed, forever if unset
+    pub lifetime: Option<Distribution>,
+    pub color: ColorPolicy,
+    pub material: u32,
+    pub phase: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum Shape {
+    /// A row of particles `spacing` apart across the velocity
+    #[default]
+    Line,
+    /// A row like `Line` with the directions fanned out over `spread` radians,
+    /// a point source for a width of one
+    Fan { spread: f32 },
+    /// Random free spots in a `size` rectangle centered on the emitter, `spacing` is unused
+    Area { size: Vec2 },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Distribution {
+    Uniform(f32, f32),
+    /// Cut off two deviations from the mean
+    Normal {
+        mean: f32,
+        deviation: f32,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub enum ColorPolicy {
+    /// A palette color per particle
+    #[default]
+    Random,
+    Fixed(Color),
+    /// Runs through the hues once every `period` seconds, a burst shares one color
+    Rainbow {
+        period: f32,
+    },
+}
+
+/// Progress of an emitter, saved with the simulation so replays spawn the same particles.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct EmitterState {
+    bursts: u64,
+    emitted: usize,
+}
+
+pub struct Spawn {
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub radius: f32,
+}
+
+// Tries per particle to find a free spot in an area.
+const ATTEMPTS: u32 = 8;
+// Wide normal distributions can reach zero, which the gpu uses to mark removed particles.
+const MIN_RADIUS: f32 = 0.05;
+
+impl Emitter {
+    /// Up to `count` particles of a burst at `time`. Area emitters only use spots `is_free`
+    /// accepts and that don't overlap the rest of the burst.
+    pub fn burst(
+        &self,
+        time: f64,
+        count: usize,
+        rng: &mut impl Rng,
+        is_free: impl Fn(Vec2, f32) -> bool,
+    ) -> Vec<Spawn> {
+        let mut direction = Vec2::X;
+        if self.sweep_period > 0.0 {
+            let phase = (time / self.sweep_period as f64).fract() as f32;
+            direction = Vec2::from_angle(self.sweep * (phase * 2.0 * PI).sin());
+        }
+        let velocity = direction.rotate(self.velocity);
+        let mut spawns = Vec::with_capacity(count);
+        match self.shape {
+            Shape::Line | Shape::Fan { .. } => {
+                let offset = velocity.perp().normalize_or_zero() * self.spacing;
+                for i in 0..count {
+                    let angle = match self.shape {
+                        Shape::Fan { spread } if self.width > 1 => {
+                            spread * (i as f32 / (self.width - 1) as f32 - 0.5)
+                        }
+                        _ => 0.0,
+                    };
+                    spawns.push(Spawn {
+                        position: self.position + offset * i as f32,
+                        velocity: self.jitter(Vec2::from_angle(angle).rotate(velocity), rng),
+                        radius: self.sample_radius(rng),
+                    });
+                }
+            }
+            Shape::Area { size } => {
+                for _ in 0..count {
+                    let radius = self.sample_radius(rng);
+                    let free = (0..ATTEMPTS).find_map(|_| {
+                        let position = self.position
+                            + size * vec2(rng.gen_range(-0.5..0.5), rng.gen_range(-0.5..0.5));
+                        let overlaps = spawns
+                            .iter()
+                            .any(|it: &Spawn| it.position.distance(position) < it.radius + radius);
+                        (!overlaps && is_free(position, radius)).then_some(position)
+                    });
+                    if let Some(position) = free {
+                        spawns.push(Spawn {
+                            position,
+                            velocity: self.jitter(velocity, rng),
+                            radius,
+                        });
+                    }
+                }
+            }
+        }
+        spawns
+    }
+
+    fn sample_radius(&self, rng: &mut impl Rng) -> f32 {
+        self.radius.sample(rng).max(MIN_RADIUS)
+    }
+
+    fn jitter(&self, velocity: Vec2, rng: &mut impl Rng) -> Vec2 {
+        let mut velocity = velocity;
+        if self.angle_jitter > 0.0 {
+            let angle = rng.gen_range(-self.angle_jitter..=self.angle_jitter);
+            velocity = Vec2::from_angle(angle).rotate(velocity);
+        }
+        if self.speed_jitter > 0.0 {
+            velocity *= 1.0 + rng.gen_range(-self.speed_jitter..=self.speed_jitter);
+        }
+        velocity
+    }
+}
+
+impl Distribution {
+    pub fn sample(&self, rng: &mut impl Rng) -> f32 {
+        match *self {
+            Distribution::Uniform(min, max) => rng.gen_range(min..=max),
+            Distribution::Normal { mean, deviation } => {
+                // Box-Muller
+                let (u, v) = (1.0 - rng.gen::<f32>(), rng.gen::<f32>());
+                let z = (-2.0 * u.ln()).sqrt() * (2.0 * PI * v).cos();
+                mean + deviation * z.clamp(-2.0, 2.0)
+            }
+        }
+    }
+
+    pub fn max(&self) -> f32 {
+        match *self {
+            Distribution::Uniform(min, max) => min.max(max),
+            Distribution::Normal { mean, deviation } => mean + 2.0 * deviation.abs(),
+        }
+    }
+}
+
+impl ColorPolicy {
+    /// None leaves the particle its random color.
+    pub fn color(&self, time: f64) -> Option<Color> {
+        match *self {
+            ColorPolicy::Random => None,
+            ColorPolicy::Fixed(color) => Some(color),
+            ColorPolicy::Rainbow { period } => {
+                let hue = if period > 0.0 {
+                    (time / period as f64).fract() as f32
+                } else {
+                    0.0
+                };
+                Some(hue_to_color(hue))
+            }
+        }
+    }
+}
+
+impl EmitterState {
+    /// How many particles the emitter may spawn at `time`, 0 unless a burst is due.
+    pub fn due(&mut self, emitter: &Emitter, time: f64) -> usize {
+        let elapsed = time - emitter.start as f64;
+        if emitter.rate <= 0.0
+            || elapsed < 0.0
+            || emitter.stop.is_some_and(|stop| time >= stop as f64)
+        {
+            return 0;
+        }
+        let bursts = elapsed * emitter.rate as f64;
+        if self.bursts as f64 > bursts {
+            return 0;
+        }
+        // bursts it fell behind on are dropped, they would land on top of each other
+        self.bursts = bursts as u64 + 1;
+        let left = emitter
+            .budget
+            .map_or(usize::MAX, |it| it.saturating_sub(self.emitted));
+        left.min(emitter.width as usize)
+    }
+
+    pub fn emitted(&mut self, count: usize) {
+        self.emitted += count;
+    }
+}
+
+impl Default for Emitter {
+    fn default() -> Self {
+        Self {
+            shape: Shape::Line,
+            position: vec2(-170.0, 40.0),
+            velocity: vec2(70.0, 0.0),
+            width: 95,
+            spacing: 2.0,
+            rate: 30.0,
+            start: 0.0,
+            stop: None,
+            budget: None,
+            sweep: 0.0,
+            sweep_period: 4.0,
+            speed_jitter: 0.0,
+            angle_jitter: 0.0,
+            radius: Distribution::Uniform(1.0, 1.0),
+            lifetime: None,
+            color: ColorPolicy::Random,
+            material: 0,
+            phase: 0,
+        }
+    }
+}
+
+// Fully saturated, `hue` in 0..1.
+fn hue_to_color(hue: f32) -> Color {
+    let channel = |n: f32| {
+        let k = (n + hue * 6.0) % 6.0;
+        1.0 - k.min(4.0 - k).clamp(0.0, 1.0)
+    };
+    Color {
+        r: channel(5.0),
+        g: channel(3.0),
+        b: channel(1.0),
+    }
+}
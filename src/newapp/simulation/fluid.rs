@@ -0,0 +1,254 @@
+use std::f32::consts::PI;
+
+use glam::Vec2;
+use rayon::ThreadPool;
+
+use super::{
+    phase::{Interactions, Rule},
+    scene::{self, Fluid, Material},
+    spatial_hash::{Neighbourhood, SpatialGrid},
+    Particle,
+};
+
+/// Position based fluids as in Macklin and Müller 2013. Fluid particles keep the mass per unit
+/// area around them at their material's density instead of colliding with each other.
+#[derive(Default)]
+pub struct FluidSolver {
+    // per particle, None for the ones that aren't fluid
+    properties: Vec<Option<Properties>>,
+    // fluid neighbours within the kernel radius at the start of the substep
+    neighbours: Vec<Vec<u32>>,
+    states: Vec<State>,
+    // position corrections, then velocity changes
+    changes: Vec<Vec2>,
+}
+
+#[derive(Clone, Copy)]
+struct Properties {
+    fluid: Fluid,
+    rest_density: f32,
+    mass: f32,
+}
+
+#[derive(Default, Clone, Copy)]
+struct State {
+    density: f32,
+    lambda: f32,
+}
+
+impl FluidSolver {
+    /// Particles move little within a substep, so its iterations share the neighbours.
+    pub fn find_neighbours<Grid: SpatialGrid + Sync>(
+        &mut self,
+        particles: &[Particle],
+        neighbourhood: &Neighbourhood<Grid>,
+        materials: &[Material],
+        interactions: &Interactions,
+        thread_pool: &ThreadPool,
+        num_threads: usize,
+    ) {
+        let len = particles.len();
+        self.properties.clear();
+        self.properties
+            .extend(particles.iter().map(|it| properties(it, materials)));
+        self.neighbours.resize_with(len, Vec::new);
+        self.states.resize(len, State::default());
+        self.changes.resize(len, Vec2::ZERO);
+        let properties = &self.properties;
+        for_each_chunk(
+            thread_pool,
+            num_threads,
+            &mut self.neighbours,
+            |i, neighbours| {
+                neighbours.clear();
+                let Some(first) = properties[i] else {
+                    return;
+                };
+                let particle = &particles[i];
+                neighbourhood.for_each(particle.position, |j| {
+                    let neighbour = &particles[j];
+                    if properties[j].is_some()
+                        && particle.position.distance_squared(neighbour.position)
+                            < first.fluid.kernel_radius * first.fluid.kernel_radius
+                        && (i == j
+                            || interactions.rule(particle, neighbour, materials) == Rule::Fluid)
+                    {
+                        neighbours.push(j as u32);
+                    }
+                });
+            },
+        );
+    }
+
+    /// One Jacobi iteration of the density constraints, only pushes apart, a fluid with too few
+    /// neighbours is left to gravity and the surface tension.
+    pub fn solve_densities(
+        &mut self,
+        particles: &mut [Particle],
+        thread_pool: &ThreadPool,
+        num_threads: usize,
+    ) {
+        let particles_ref = &*particles;
+        let properties = &self.properties;
+        let neighbours = &self.neighbours;
+        for_each_chunk(thread_pool, num_threads, &mut self.states, |i, state| {
+            *state = State::default();
+            let Some(first) = properties[i] else {
+                return;
+            };
+            let h = first.fluid.kernel_radius;
+            let position = particles_ref[i].position;
+            let mut sum = 0.0;
+            // gradients of the constraint with respect to the particle and its neighbours
+            let mut gradient = Vec2::ZERO;
+            let mut neighbour_gradients = 0.0;
+            for &j in &neighbours[i] {
+                let j = j as usize;
+                let second = properties[j].unwrap();
+                let offset = position - particles_ref[j].position;
+                let distance = offset.length();
+                if distance >= h {
+                    continue;
+                }
+                sum += second.mass * poly6(distance, h);
+                if i != j {
+                    let it =
+                        spiky_gradient(offset, distance, h) * (second.mass / first.rest_density);
+                    gradient += it;
+                    neighbour_gradients += it.length_squared() / second.mass;
+                }
+            }
+            let constraint = sum / first.rest_density - 1.0;
+            state.density = sum;
+            state.lambda = if constraint > 0.0 {
+                -constraint
+                    / (gradient.length_squared() / first.mass
+                        + neighbour_gradients
+                        + first.fluid.relaxation)
+            } else {
+                0.0
+            };
+        });
+
+        let states = &self.states;
+        for_each_chunk(thread_pool, num_threads, &mut self.changes, |i, change| {
+            *change = Vec2::ZERO;
+            let Some(first) = properties[i] else {
+                return;
+            };
+            let h = first.fluid.kernel_radius;
+            let position = particles_ref[i].position;
+            for &j in &neighbours[i] {
+                let j = j as usize;
+                let second = properties[j].unwrap();
+                let offset = position - particles_ref[j].position;
+                let distance = offset.length();
+                if i == j || distance >= h {
+                    continue;
+                }
+                let scale = states[i].lambda * second.mass / (first.mass * first.rest_density)
+                    + states[j].lambda / second.rest_density;
+                *change += spiky_gradient(offset, distance, h) * scale;
+            }
+        });
+        for (particle, change) in particles.iter_mut().zip(&self.changes) {
+            particle.position += *change;
+        }
+    }
+
+    /// XSPH viscosity and surface tension, once the velocities have been derived from
+    /// the positions. Uses the densities of the last iteration.
+    pub fn apply_velocities(
+        &mut self,
+        particles: &mut [Particle],
+        dt: f32,
+        thread_pool: &ThreadPool,
+        num_threads: usize,
+    ) {
+        let particles_ref = &*particles;
+        let properties = &self.properties;
+        let neighbours = &self.neighbours;
+        let states = &self.states;
+        for_each_chunk(thread_pool, num_threads, &mut self.changes, |i, change| {
+            *change = Vec2::ZERO;
+            let Some(first) = properties[i] else {
+                return;
+            };
+            let h = first.fluid.kernel_radius;
+            let particle = &particles_ref[i];
+            let mut viscosity = Vec2::ZERO;
+            let mut tension = Vec2::ZERO;
+            for &j in &neighbours[i] {
+                let j = j as usize;
+                let second = properties[j].unwrap();
+                let neighbour = &particles_ref[j];
+                let offset = particle.position - neighbour.position;
+                let distance = offset.length();
+                let density = states[j].density;
+                if i == j || distance >= h || density <= 0.0 {
+                    continue;
+                }
+                viscosity += (neighbour.velocity - particle.velocity)
+                    * (second.mass / density * poly6(distance, h));
+                // the pair feels the same force, lighter particles move more
+                tension -= offset.normalize_or_zero()
+                    * (cohesion(distance, h) * 2.0 * second.mass / (first.mass + second.mass));
+            }
+            *change =
+                viscosity * first.fluid.viscosity + tension * (first.fluid.surface_tension * dt);
+        });
+        for (particle, change) in particles.iter_mut().zip(&self.changes) {
+            particle.velocity += *change;
+        }
+    }
+}
+
+fn properties(particle: &Particle, materials: &[Material]) -> Option<Properties> {
+    let material = scene::material(materials, particle.material);
+    material.fluid.map(|fluid| Properties {
+        fluid,
+        rest_density: material.density,
+        mass: 1.0 / material.inverse_mass(particle.radius),
+    })
+}
+
+// Kernels normalized in 2D, poly6 for densities and the gradient of spiky for pressure.
+fn poly6(distance: f32, h: f32) -> f32 {
+    let x = h * h - distance * distance;
+    4.0 / (PI * h.powi(8)) * x * x * x
+}
+
+fn spiky_gradient(offset: Vec2, distance: f32, h: f32) -> Vec2 {
+    let x = h - distance;
+    offset.normalize_or_zero() * (-30.0 / (PI * h.powi(5)) * x * x)
+}
+
+// Akinci et al. 2013 scaled to 1 at its peak, attracts beyond h / 2 and repels closer.
+fn cohesion(distance: f32, h: f32) -> f32 {
+    let x = (h - distance) * distance;
+    let spline = 64.0 * x * x * x / h.powi(6);
+    if distance > h * 0.5 {
+        spline
+    } else {
+        2.0 * spline - 1.0
+    }
+}
+
+fn for_each_chunk<T: Send>(
+    thread_pool: &ThreadPool,
+    num_threads: usize,
+    items: &mut [T],
+    f: impl Fn(usize, &mut T) + Sync,
+) {
+    let chunk_size = items.len().div_ceil(num_threads).max(1);
+    let f = &f;
+    thread_pool.scope(|s| {
+        for (n, chunk) in items.chunks_mut(chunk_size).enumerate() {
+            s.spawn(move |_| {
+                for (i, it) in chunk.iter_mut().enumerate() {
+                    f(n * chunk_size + i, it);
+                }
+            });
+        }
+    });
+}
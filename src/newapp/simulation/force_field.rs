@@ -0,0 +1,132 @@
+use glam::{vec2, Vec2};
+use serde::{Deserialize, Serialize};
+
+/// Accelerations on top of gravity, they act the same on light and heavy particles.
+/// Radial fields and vortices are strongest at `center` and fade out linearly at `radius`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ForceField {
+    /// Wind, or gravity in a second direction
+    Uniform { acceleration: Vec2 },
+    /// Pulls towards `center`, a negative `strength` pushes away
+    Radial {
+        center: Vec2,
+        radius: f32,
+        strength: f32,
+    },
+    /// Spins around `center`, counter-clockwise for a positive `strength`
+    Vortex {
+        center: Vec2,
+        radius: f32,
+        strength: f32,
+    },
+    /// Turbulence with swirls about `scale` wide that change `speed` times a second
+    Noise {
+        strength: f32,
+        scale: f32,
+        speed: f32,
+    },
+    /// Slows particles by `linear * velocity + quadratic * |velocity| * velocity`
+    Drag { linear: f32, quadratic: f32 },
+}
+
+impl ForceField {
+    /// Velocity change over `dt` for a particle at `position`, `time` in seconds since the start.
+    pub fn velocity_change(&self, position: Vec2, velocity: Vec2, time: f32, dt: f32) -> Vec2 {
+        match *self {
+            ForceField::Uniform { acceleration } => acceleration * dt,
+            ForceField::Radial {
+                center,
+                radius,
+                strength,
+            } => {
+                let offset = center - position;
+                offset.normalize_or_zero() * (strength * falloff(offset, radius) * dt)
+            }
+            ForceField::Vortex {
+                center,
+                radius,
+                strength,
+            } => {
+                let offset = position - center;
+                offset.perp().normalize_or_zero() * (strength * falloff(offset, radius) * dt)
+            }
+            ForceField::Noise {
+                strength,
+                scale,
+                speed,
+            } => {
+                let point = position / scale;
+                let t = time * speed;
+                vec2(noise(point, t, 0), noise(point, t, 1)) * (strength * dt)
+            }
+            // integrated exactly, so strong drag stops particles instead of turning them around
+            ForceField::Drag { linear, quadratic } => {
+                let rate = linear + quadratic * velocity.length();
+                velocity * ((-rate * dt).exp() - 1.0)
+            }
+        }
+    }
+}
+
+/// Sum of the velocity changes of all `fields`.
+pub fn velocity_change<'a>(
+    fields: impl IntoIterator<Item = &'a ForceField>,
+    position: Vec2,
+    velocity: Vec2,
+    time: f32,
+    dt: f32,
+) -> Vec2 {
+    fields
+        .into_iter()
+        .map(|it| it.velocity_change(position, velocity, time, dt))
+        .sum()
+}
+
+fn falloff(offset: Vec2, radius: f32) -> f32 {
+    (1.0 - offset.length() / radius).max(0.0)
+}
+
+// Value noise in -1..1 over space and time, smoothly interpolated between random values on
+// the integer lattice. Kept in sync with `noise` in compute.wgsl.
+fn noise(point: Vec2, time: f32, channel: u32) -> f32 {
+    let cell = point.floor();
+    let frame = time.floor();
+    let (x, y, z) = (cell.x as i32, cell.y as i32, frame as i32);
+    let fraction = point - cell;
+    let (u, v, w) = (
+        smoothstep(fraction.x),
+        smoothstep(fraction.y),
+        smoothstep(time - frame),
+    );
+    let layer = |z: i32| {
+        let bottom = lerp(hash(x, y, z, channel), hash(x + 1, y, z, channel), u);
+        let top = lerp(
+            hash(x, y + 1, z, channel),
+            hash(x + 1, y + 1, z, channel),
+            u,
+        );
+        lerp(bottom, top, v)
+    };
+    lerp(layer(z), layer(z + 1), w)
+}
+
+fn hash(x: i32, y: i32, z: i32, channel: u32) -> f32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6b343)
+        ^ (y as u32).wrapping_mul(0xd8163841)
+        ^ (z as u32).wrapping_mul(0xcb1ab31f)
+        ^ channel.wrapping_mul(0x9e3779b9);
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846ca68b);
+    h ^= h >> 16;
+    (h >> 8) as f32 / (1 << 23) as f32 - 1.0
+}
+
+fn smoothstep(x: f32) -> f32 {
+    x * x * (3.0 - 2.0 * x)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
@@ -0,0 +1,102 @@
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    scene::{self, Link, Material},
+    Particle,
+};
+
+/// What the second end of a joint is attached to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Anchor {
+    /// Initial id of the particle
+    Particle(usize),
+    /// A fixed point, pins the first particle
+    Point(Vec2),
+}
+
+/// Keeps the particle with initial id `first` within `link.min..=link.max` times
+/// `rest_length` of `second`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Joint {
+    pub first: usize,
+    pub second: Anchor,
+    pub rest_length: f32,
+    pub link: Link,
+    // XPBD multiplier accumulated over the iterations of a substep, the force is lambda / dt^2
+    lambda: f32,
+}
+
+impl Joint {
+    pub fn new(first: usize, second: Anchor, rest_length: f32, link: Link) -> Self {
+        Self {
+            first,
+            second,
+            rest_length,
+            link,
+            lambda: 0.0,
+        }
+    }
+
+    pub(super) fn reset(&mut self) {
+        self.lambda = 0.0;
+    }
+
+    pub(super) fn is_broken(&self, dt: f32) -> bool {
+        self.link.max_force > 0.0 && self.lambda.abs() / (dt * dt) > self.link.max_force
+    }
+
+    /// `indexes` maps initial ids to indexes into `particles`.
+    pub(super) fn solve(
+        &mut self,
+        particles: &mut [Particle],
+        indexes: &[usize],
+        materials: &[Material],
+        dt: f32,
+    ) {
+        let first = indexes[self.first];
+        let weight = |particle: &Particle| {
+            scene::material(materials, particle.material).inverse_mass(particle.radius)
+        };
+        let first_weight = weight(&particles[first]);
+        let (second, second_position, second_weight) = match self.second {
+            Anchor::Particle(id) => {
+                let second = indexes[id];
+                let particle = &particles[second];
+                (Some(second), particle.position, weight(particle))
+            }
+            Anchor::Point(point) => (None, point, 0.0),
+        };
+        let vector = second_position - particles[first].position;
+        let distance = vector.length();
+        let min = self.rest_length * self.link.min;
+        let max = self.rest_length * self.link.max;
+        let constraint = if distance < min {
+            distance - min
+        } else if distance > max {
+            distance - max
+        } else {
+            0.0
+        };
+        if constraint == 0.0 && self.lambda == 0.0 {
+            return;
+        }
+        let direction = vector.normalize_or(Vec2::X);
+        let alpha = self.link.compliance / (dt * dt);
+        let delta_lambda =
+            (-constraint - alpha * self.lambda) / (first_weight + second_weight + alpha);
+        self.lambda += delta_lambda;
+        particles[first].position -= direction * delta_lambda * first_weight;
+        if let Some(second) = second {
+            particles[second].position += direction * delta_lambda * second_weight;
+        }
+    }
+
+    pub fn ends(&self, particles: &[Particle], indexes: &[usize]) -> (Vec2, Vec2) {
+        let second = match self.second {
+            Anchor::Particle(id) => particles[indexes[id]].position,
+            Anchor::Point(point) => point,
+        };
+        (particles[indexes[self.first]].position, second)
+    }
+}
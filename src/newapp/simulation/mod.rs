@@ -1,43 +1,74 @@
+pub mod boundary;
 pub mod box_constraint;
+pub mod cluster;
+pub mod emitter;
+pub mod fluid;
+pub mod force_field;
 mod integrator;
+pub mod joint;
+pub mod mouse;
+pub mod obstacle;
+pub mod phase;
+pub mod recording;
+pub mod scene;
+pub mod sink;
+pub mod snapshot;
 mod sorted_store;
 pub mod spatial_hash;
 
 use std::{
     f32::consts::PI,
     fs::{File, OpenOptions},
-    ops::Div,
-    sync::{atomic::AtomicBool, Barrier, Mutex},
+    iter::StepBy,
+    ops::{Div, Range},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Barrier, Mutex,
+    },
     time::Instant,
 };
 
-use box_constraint::BoxConstraint;
+use clap::ValueEnum;
 use glam::{uvec2, vec2, UVec2, Vec2};
 use image::{GenericImageView, Pixel};
 use itertools::Itertools;
-use rand::{rngs::StdRng, Rng, SeedableRng};
+use rand::Rng;
+use rand_chacha::ChaCha12Rng;
 use rayon::{ThreadPool, ThreadPoolBuilder};
+use cluster::Cluster;
+use emitter::{Emitter, EmitterState, Shape};
+use fluid::FluidSolver;
+use joint::{Anchor, Joint};
+use mouse::{Mouse, Tool};
+use obstacle::ObstacleGrid;
+use phase::{Interactions, Rule};
+use scene::{Link, Material, Scene};
+use snapshot::Snapshot;
 use serde::{Deserialize, Serialize};
 use spatial_hash::{
     fixed_size_grid::FixedSizeGrid,
     pointer_hash::{HashReference, PointerHash},
     sorting_hash::{Positioned, SortingHash},
-    SpatialGrid,
+    Neighbourhood, SpatialGrid,
 };
 use std::io::{self, Read, Write};
 
 use super::profiler::{self, Profiler};
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Particle {
     pub initial_id: usize,
     pub position: Vec2,
     pub velocity: Vec2,
     pub radius: f32,
+    pub material: u32,
+    /// Particles of the same rigid body don't collide with each other, 0 for loose ones
+    pub body: u32,
+    /// Picks the interaction rules with other particles
+    pub phase: u32,
 }
 
-const BOUND_RADIUS: f32 = 300.0;
-const MAX_PARTICLE_RADIUS: f32 = 1.0;
-
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub struct Color {
     pub r: f32,
@@ -45,66 +76,227 @@ pub struct Color {
     pub b: f32,
 }
 
+/// How particle pairs are found and split between threads.
+#[derive(ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CollisionMode {
+    /// Pointer hash, even and odd rows in two phases separated by a barrier
+    #[default]
+    Stagger,
+    /// Pointer hash, threads own row chunks and only wait on the shared boundary rows
+    RowChunks,
+    /// Particles are reordered by cell so each cell is a contiguous slice
+    Sorted,
+}
+
 pub struct Simulation {
     particles: Vec<Particle>,
     previous_positions: Vec<Vec2>,
+    // velocities before the constraints of the current substep, for restitution
+    previous_velocities: Vec<Vec2>,
     updates: u64,
-    rng: StdRng,
+    // seconds simulated, drives the obstacle motion
+    time: f64,
+    rng: ChaCha12Rng,
     spatial_hash: PointerHash<FixedSizeGrid>,
     sorting_hash: SortingHash<FixedSizeGrid>,
+    obstacle_grid: ObstacleGrid<FixedSizeGrid>,
     pub colors: Vec<Color>,
     colors_changed: bool,
-    collision_detection_mode: u32,
+    collision_detection_mode: CollisionMode,
     elapsed: Option<f64>,
     thread_pool: ThreadPool,
+    num_threads: usize,
+    scene: Scene,
+    interactions: Interactions,
+    row_contacts: Vec<RowContacts>,
+    fluid_solver: FluidSolver,
+    joints: Vec<Joint>,
+    clusters: Vec<Cluster>,
+    // current index of every particle by initial id, joints and clusters refer to particles by id
+    indexes: Vec<usize>,
+    // initial ids of removed particles, handed out again before new ones
+    free_ids: Vec<usize>,
+    // particles removed since the start, recordings tell spawns and removals apart with it
+    removed_particles: u64,
+    mouse: Mouse,
+    // one per scene emitter
+    emitters: Vec<EmitterState>,
+    // seconds at which each particle is removed by initial id, infinite for most
+    expiry: Vec<f64>,
 }
 
-const NUM_THREADS: usize = 4;
+/// Accumulated Lagrange multipliers of the candidate pairs of one grid row, in the order they
+/// are visited. Cells don't change within an update, so every iteration visits the same pairs.
+#[derive(Default)]
+struct RowContacts {
+    lambdas: Vec<f32>,
+    next: usize,
+}
 
 impl Simulation {
-    pub fn new() -> Self {
-        let seed: [u8; 32] = [
-            1u8, 2u8, 3u8, 4u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-            0, 0, 0, 0, 0, 0,
-        ];
-        let rng: StdRng = SeedableRng::from_seed(seed);
+    pub fn new(scene: Scene, num_threads: usize) -> Self {
+        let rng = scene.rng();
         let particles = vec![];
         let colors = load_vector_from_file("colors.bin")
             .unwrap()
             .unwrap_or(vec![]);
 
-        let min_cell_size = MAX_PARTICLE_RADIUS * 2.0;
-        let grid = FixedSizeGrid::new(min_cell_size, BoxConstraint::around_center(BOUND_RADIUS));
+        let grid = FixedSizeGrid::new(scene.cell_size(), scene.bounds);
 
-        Self {
+        let mut simulation = Self {
             particles,
             previous_positions: vec![],
+            previous_velocities: vec![],
             updates: 0,
+            time: 0.0,
             rng,
             spatial_hash: PointerHash::new(grid.clone()),
+            obstacle_grid: ObstacleGrid::new(grid.clone()),
             sorting_hash: SortingHash::new(grid),
             colors,
             colors_changed: true,
-            collision_detection_mode: 0,
+            collision_detection_mode: CollisionMode::default(),
             elapsed: None,
             thread_pool: ThreadPoolBuilder::new()
-                .num_threads(NUM_THREADS)
+                .num_threads(num_threads.max(1))
                 .build()
                 .unwrap(),
-        }
+            num_threads: num_threads.max(1),
+            interactions: Interactions::new(&scene.interactions),
+            scene,
+            row_contacts: vec![],
+            fluid_solver: FluidSolver::default(),
+            joints: vec![],
+            clusters: vec![],
+            indexes: vec![],
+            free_ids: vec![],
+            removed_particles: 0,
+            mouse: Mouse::default(),
+            emitters: vec![],
+            expiry: vec![],
+        };
+        simulation.spawn_blocks();
+        simulation.spawn_chains();
+        simulation.spawn_soft_bodies();
+        simulation.spawn_rigid_bodies();
+        simulation.update_indexes();
+        simulation
     }
 
     pub fn toggle_collision_detection_mode(&mut self) {
-        self.collision_detection_mode = (self.collision_detection_mode + 1) % 2;
-        println!("Collision mode: {}", self.collision_detection_mode);
+        let modes = CollisionMode::value_variants();
+        let current = modes
+            .iter()
+            .position(|it| *it == self.collision_detection_mode)
+            .unwrap();
+        self.set_collision_detection_mode(modes[(current + 1) % modes.len()]);
+    }
+
+    pub fn set_collision_detection_mode(&mut self, mode: CollisionMode) {
+        self.collision_detection_mode = mode;
+        println!("Collision mode: {mode:?}");
     }
 
-    pub fn on_mouse_move(&mut self, _position: Vec2) {}
+    pub fn apply_scene(&mut self, scene: Scene) {
+        let grid_changed = scene.bounds != self.scene.bounds
+            || scene.cell_size() != self.scene.cell_size();
+        if grid_changed {
+            let grid = FixedSizeGrid::new(scene.cell_size(), scene.bounds);
+            self.spatial_hash = PointerHash::new(grid.clone());
+            self.sorting_hash = SortingHash::new(grid);
+        }
+        // the obstacles may have changed as well
+        self.obstacle_grid = ObstacleGrid::new(self.spatial_hash.grid().clone());
+        self.interactions = Interactions::new(&scene.interactions);
+        self.scene = scene;
+    }
+
+    pub fn scene(&self) -> &Scene {
+        &self.scene
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            scene: self.scene.clone(),
+            particles: self.particles.clone(),
+            previous_positions: self.previous_positions.clone(),
+            updates: self.updates,
+            time: self.time,
+            rng: self.rng.clone(),
+            colors: self.colors.clone(),
+            collision_detection_mode: self.collision_detection_mode,
+            joints: self.joints.clone(),
+            clusters: self.clusters.clone(),
+            free_ids: self.free_ids.clone(),
+            mouse: self.mouse.clone(),
+            emitters: self.emitters.clone(),
+            expiry: self.expiry.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: Snapshot) {
+        self.apply_scene(snapshot.scene);
+        self.particles = snapshot.particles;
+        self.previous_positions = snapshot.previous_positions;
+        self.updates = snapshot.updates;
+        self.time = snapshot.time;
+        self.rng = snapshot.rng;
+        self.colors = snapshot.colors;
+        self.colors_changed = true;
+        self.collision_detection_mode = snapshot.collision_detection_mode;
+        self.joints = snapshot.joints;
+        self.clusters = snapshot.clusters;
+        self.free_ids = snapshot.free_ids;
+        self.mouse = snapshot.mouse;
+        self.emitters = snapshot.emitters;
+        self.expiry = snapshot.expiry;
+        self.update_indexes();
+    }
+
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.snapshot().save(path)
+    }
+
+    pub fn load_snapshot(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.restore(Snapshot::load(path)?);
+        Ok(())
+    }
+
+    pub fn on_mouse_move(&mut self, position: Vec2) {
+        self.mouse.position = position;
+    }
+
+    pub fn on_mouse_button(&mut self, pressed: bool) {
+        self.mouse.pressed = pressed;
+        self.update_grab();
+    }
+
+    pub fn select_tool(&mut self, tool: Tool) {
+        self.mouse.tool = tool;
+        self.update_grab();
+    }
+
+    pub fn mouse(&self) -> &Mouse {
+        &self.mouse
+    }
+
+    fn update_grab(&mut self) {
+        self.mouse.release();
+        if self.mouse.is_using(Tool::Grab) {
+            self.mouse.grab(&self.particles, &self.scene.mouse);
+        }
+    }
 
     pub fn get_particles(&self) -> &Vec<Particle> {
         &self.particles
     }
 
+    pub fn joint_ends(&self) -> impl Iterator<Item = (Vec2, Vec2)> + '_ {
+        self.joints
+            .iter()
+            .map(|it| it.ends(&self.particles, &self.indexes))
+    }
+
     pub fn get_colors(&mut self) -> Option<&Vec<Color>> {
         if self.colors_changed {
             self.colors_changed = false;
@@ -116,49 +308,131 @@ impl Simulation {
 
     pub fn update(&mut self, dt: f32, profiler: &mut Profiler) {
         self.spawn();
-        let steps = 8;
+        self.apply_mouse_tools();
+        self.remove_expired();
+        let steps = self.scene.substeps.max(1);
 
         match self.collision_detection_mode {
-            _ => {
+            CollisionMode::Stagger | CollisionMode::RowChunks => {
                 profiler.start(profiler::Kind::BulidSpatialHash);
                 self.spatial_hash
                     .build(self.particles.iter().map(|it| &it.position));
                 profiler.end(profiler::Kind::BulidSpatialHash);
             }
-            _ => {
+            // Sorting in place keeps the particles of neighbouring cells close in memory,
+            // previous positions are rewritten before they are read again so they can stay as they are.
+            CollisionMode::Sorted => {
                 profiler.start(profiler::Kind::Sort);
                 self.sorting_hash.build(&mut self.particles);
                 profiler.end(profiler::Kind::Sort);
-            } // 0 => self.particles.sort_by_cached_key(|p| {
-              //     self.spatial_hash.grid().get_position_cell_index(p.position)
-              // }),
-              // _ => self.particles.sort_unstable_by_key(|p| {
-              //     self.spatial_hash.grid().get_position_cell_index(p.position)
-              // }),
+            }
         }
+        self.update_indexes();
+        let height = self.spatial_hash.grid().size().y as usize;
+        self.row_contacts.resize_with(height, RowContacts::default);
+        let has_fluid = self.scene.materials.iter().any(|it| it.fluid.is_some());
         {
             let dt = dt / steps as f32;
-            for _ in 0..steps {
+            for step in 0..steps {
                 profiler.start(profiler::Kind::UpdateParticles);
+                self.time += dt as f64;
+                self.obstacle_grid.build(
+                    &self.scene.obstacles,
+                    self.time,
+                    self.scene.max_particle_radius() + self.scene.max_cohesion_range(),
+                );
                 self.update_particles(dt);
+                self.mouse.hold(
+                    &mut self.particles,
+                    &self.indexes,
+                    (step + 1) as f32 / steps as f32,
+                );
                 profiler.end(profiler::Kind::UpdateParticles);
                 profiler.start(profiler::Kind::CollisionDetectionAndResolution);
-                self.apply_distance_constraints(dt);
+                self.row_contacts.iter_mut().for_each(RowContacts::clear);
+                self.joints.iter_mut().for_each(Joint::reset);
+                if has_fluid {
+                    self.fluid_solver.find_neighbours(
+                        &self.particles,
+                        &neighbourhood(
+                            self.collision_detection_mode,
+                            &self.spatial_hash,
+                            &self.sorting_hash,
+                        ),
+                        &self.scene.materials,
+                        &self.interactions,
+                        &self.thread_pool,
+                        self.num_threads,
+                    );
+                }
+                let iterations = self.scene.iterations.max(1);
+                for _ in 0..iterations {
+                    self.row_contacts.iter_mut().for_each(RowContacts::rewind);
+                    self.apply_distance_constraints(dt, Pass::Positions);
+                    if has_fluid {
+                        self.fluid_solver.solve_densities(
+                            &mut self.particles,
+                            &self.thread_pool,
+                            self.num_threads,
+                        );
+                    }
+                    for joint in &mut self.joints {
+                        joint.solve(
+                            &mut self.particles,
+                            &self.indexes,
+                            &self.scene.materials,
+                            dt,
+                        );
+                    }
+                    for cluster in &self.clusters {
+                        cluster.solve(
+                            &mut self.particles,
+                            &self.indexes,
+                            &self.scene.materials,
+                            iterations,
+                        );
+                    }
+                }
+                self.joints.retain(|it| !it.is_broken(dt));
                 // self.apply_fancy(dt);
                 profiler.end(profiler::Kind::CollisionDetectionAndResolution);
+                self.previous_velocities.clear();
                 for (particle, previous_position) in self
                     .particles
                     .iter_mut()
                     .zip(self.previous_positions.iter())
                 {
+                    self.previous_velocities.push(particle.velocity);
                     particle.velocity = (particle.position - previous_position) / dt;
-                    let damp = 100.0 / particle.velocity.length();
+                    particle.velocity *= (1.0 - self.scene.damping * dt).max(0.0);
+                    let damp = self.scene.max_speed / particle.velocity.length();
                     if damp < 1.0 {
                         particle.velocity *= damp;
                     }
                 }
+                if self.scene.materials.iter().any(|it| it.restitution > 0.0) {
+                    profiler.start(profiler::Kind::CollisionDetectionAndResolution);
+                    self.apply_distance_constraints(dt, Pass::Velocities);
+                    profiler.end(profiler::Kind::CollisionDetectionAndResolution);
+                }
+                if has_fluid {
+                    self.fluid_solver.apply_velocities(
+                        &mut self.particles,
+                        dt,
+                        &self.thread_pool,
+                        self.num_threads,
+                    );
+                }
+                for cluster in self.clusters.iter().filter(|it| it.is_rigid()) {
+                    cluster.apply_rigid_motion(
+                        &mut self.particles,
+                        &self.indexes,
+                        &self.scene.materials,
+                    );
+                }
             }
         }
+        self.mouse.moved();
         self.updates += 1;
     }
 
@@ -244,44 +518,364 @@ impl Simulation {
     // }
 
     fn spawn(&mut self) {
-        // let count = 8900;
-        // let count = 35200; //175
-        let count = 107500;
-        if self.particles.len() < count && self.updates % 2 == 0 {
-            let velocity =
-                // glam::Vec2::from_angle(f32::sin(self.updates as f32 / 40.0) * PI * 0.125) * 80.0;
-                vec2(70.0, 0.0);
-            let offset = velocity.perp().normalize() * 2.0;
-            for i in 0..95 {
-                self.particles.push(Particle {
-                    initial_id: self.particles.len(),
-                    position: vec2(-170.0, 40.0) + offset * i as f32,
-                    radius: self.rng.get_random_size(),
-                    velocity,
-                });
-                if self.particles.len() > self.colors.len() {
+        let emitters = &self.scene.emitters;
+        self.emitters
+            .resize(emitters.len(), EmitterState::default());
+        for i in 0..emitters.len() {
+            let emitter = &self.scene.emitters[i];
+            let room = self
+                .scene
+                .max_particles
+                .saturating_sub(self.particles.len());
+            let count = self.emitters[i].due(emitter, self.time).min(room);
+            if count == 0 {
+                continue;
+            }
+            let mut nearby = vec![];
+            if let Shape::Area { size } = emitter.shape {
+                let reach = size / 2.0 + self.scene.max_particle_radius() * 2.0;
+                nearby.extend(
+                    self.particles
+                        .iter()
+                        .filter(|it| (it.position - emitter.position).abs().cmple(reach).all())
+                        .map(|it| (it.position, it.radius)),
+                );
+            }
+            let spawns = emitter.burst(self.time, count, &mut self.rng, |position, radius| {
+                nearby
+                    .iter()
+                    .all(|(other, r)| position.distance(*other) >= radius + r)
+            });
+            let Emitter {
+                material,
+                phase,
+                color,
+                lifetime,
+                ..
+            } = *emitter;
+            let color = color.color(self.time);
+            self.emitters[i].emitted(spawns.len());
+            for spawn in spawns {
+                self.push_particle(
+                    spawn.position,
+                    spawn.velocity,
+                    spawn.radius,
+                    material,
+                    phase,
+                );
+                let id = self.particles.last().unwrap().initial_id;
+                if let Some(color) = color {
+                    self.colors[id] = color;
                     self.colors_changed = true;
-                    self.colors.push(self.rng.get_random_color());
                 }
+                if let Some(lifetime) = lifetime {
+                    self.expiry[id] = self.time + lifetime.sample(&mut self.rng) as f64;
+                }
+            }
+        }
+    }
+
+    fn spawn_blocks(&mut self) {
+        let blocks = self.scene.blocks.clone();
+        for block in &blocks {
+            for position in block.positions() {
+                if self.particles.len() >= self.scene.max_particles {
+                    return;
+                }
+                let radius = self.rng.get_random_size(block.radius);
+                self.push_particle(
+                    position,
+                    block.velocity,
+                    radius,
+                    block.material,
+                    block.phase,
+                );
+            }
+        }
+    }
+
+    fn spawn_chains(&mut self) {
+        let chains = self.scene.chains.clone();
+        for chain in &chains {
+            let positions = chain.positions().collect::<Vec<_>>();
+            if self.particles.len() + positions.len() > self.scene.max_particles {
+                println!("Not enough particles left for a chain, skipping it");
+                continue;
+            }
+            let first = self.particles.len();
+            for &position in &positions {
+                self.push_particle(
+                    position,
+                    Vec2::ZERO,
+                    chain.radius,
+                    chain.material,
+                    chain.phase,
+                );
+            }
+            let length = chain.length();
+            let id = |row: u32, i: u32| first + (row * length + i) as usize;
+            for row in 0..chain.width.max(1) {
+                for i in 0..length {
+                    if i + 1 < length {
+                        let next = Anchor::Particle(id(row, i + 1));
+                        self.joints
+                            .push(Joint::new(id(row, i), next, chain.spacing, chain.link));
+                    }
+                    if row + 1 < chain.width {
+                        let next = Anchor::Particle(id(row + 1, i));
+                        self.joints
+                            .push(Joint::new(id(row, i), next, chain.spacing, chain.link));
+                    }
+                }
+                // pins hold whatever the links can take
+                let pin = Link {
+                    max_force: 0.0,
+                    ..chain.link
+                };
+                for (pinned, i) in [(chain.pinned.0, 0), (chain.pinned.1, length - 1)] {
+                    if pinned {
+                        let point = Anchor::Point(positions[id(row, i) - first]);
+                        self.joints.push(Joint::new(id(row, i), point, 0.0, pin));
+                    }
+                }
+            }
+        }
+    }
+
+    fn spawn_soft_bodies(&mut self) {
+        let soft_bodies = self.scene.soft_bodies.clone();
+        for soft_body in &soft_bodies {
+            let positions = soft_body.positions();
+            if self.particles.len() + positions.len() > self.scene.max_particles {
+                println!("Not enough particles left for a soft body, skipping it");
+                continue;
+            }
+            let first = self.particles.len();
+            for position in positions {
+                self.push_particle(
+                    position,
+                    soft_body.velocity,
+                    soft_body.radius,
+                    soft_body.material,
+                    soft_body.phase,
+                );
+            }
+            self.clusters.push(Cluster::new(
+                &self.particles[first..],
+                &self.scene.materials,
+                soft_body.stiffness,
+            ));
+        }
+    }
+
+    fn spawn_rigid_bodies(&mut self) {
+        let rigid_bodies = self.scene.rigid_bodies.clone();
+        for (body, rigid_body) in rigid_bodies.iter().enumerate() {
+            let positions = rigid_body.positions();
+            if positions.is_empty() {
+                continue;
+            }
+            if self.particles.len() + positions.len() > self.scene.max_particles {
+                println!("Not enough particles left for a rigid body, skipping it");
+                continue;
+            }
+            let first = self.particles.len();
+            let center = positions.iter().sum::<Vec2>() / positions.len() as f32;
+            for position in positions {
+                let velocity =
+                    rigid_body.velocity + (position - center).perp() * rigid_body.angular_velocity;
+                self.push_particle(
+                    position,
+                    velocity,
+                    rigid_body.radius,
+                    rigid_body.material,
+                    rigid_body.phase,
+                );
+            }
+            // members share the color of the first one
+            let color = self.colors[first];
+            for particle in &mut self.particles[first..] {
+                particle.body = body as u32 + 1;
+                self.colors[particle.initial_id] = color;
+            }
+            self.colors_changed = true;
+            self.clusters.push(Cluster::new(
+                &self.particles[first..],
+                &self.scene.materials,
+                1.0,
+            ));
+        }
+    }
+
+    fn update_indexes(&mut self) {
+        if self.joints.is_empty() && self.clusters.is_empty() && !self.mouse.is_grabbing() {
+            return;
+        }
+        self.indexes
+            .resize(self.particles.len() + self.free_ids.len(), 0);
+        for (index, particle) in self.particles.iter().enumerate() {
+            self.indexes[particle.initial_id] = index;
+        }
+    }
+
+    fn apply_mouse_tools(&mut self) {
+        let tools = &self.scene.mouse;
+        let (center, radius) = (self.mouse.position, tools.radius);
+        if self.mouse.is_using(Tool::Delete) {
+            self.remove_particles(|it| it.position.distance(center) < radius);
+        }
+        if self.mouse.is_using(Tool::Paint) {
+            self.paint();
+        }
+    }
+
+    // Removes the particles in sinks and the ones that outlived their lifetime.
+    fn remove_expired(&mut self) {
+        if self.scene.sinks.is_empty() && self.expiry.iter().all(|it| it.is_infinite()) {
+            return;
+        }
+        let sinks = std::mem::take(&mut self.scene.sinks);
+        let expiry = std::mem::take(&mut self.expiry);
+        let time = self.time;
+        self.remove_particles(|it| {
+            expiry[it.initial_id] <= time || sinks.iter().any(|sink| sink.contains(it.position))
+        });
+        self.scene.sinks = sinks;
+        self.expiry = expiry;
+    }
+
+    // Drops new particles at random free spots under the cursor.
+    fn paint(&mut self) {
+        const ATTEMPTS: u32 = 8;
+        let tools = self.scene.mouse.clone();
+        let center = self.mouse.position;
+        let reach = tools.radius + self.scene.max_particle_radius() * 2.0;
+        let mut nearby = self
+            .particles
+            .iter()
+            .filter(|it| it.position.distance(center) < reach)
+            .map(|it| (it.position, it.radius))
+            .collect::<Vec<_>>();
+        for _ in 0..tools.paint_rate {
+            if self.particles.len() >= self.scene.max_particles {
+                return;
+            }
+            let radius = self.rng.get_random_size(tools.paint_radius);
+            for _ in 0..ATTEMPTS {
+                let offset = Vec2::from_angle(self.rng.gen_range(0.0..2.0 * PI))
+                    * (tools.radius * self.rng.gen::<f32>().sqrt());
+                let position = center + offset;
+                if nearby
+                    .iter()
+                    .all(|(other, r)| position.distance(*other) >= radius + r)
+                {
+                    self.push_particle(
+                        position,
+                        Vec2::ZERO,
+                        radius,
+                        tools.paint_material,
+                        tools.paint_phase,
+                    );
+                    nearby.push((position, radius));
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Removes the particles matching `remove` along with their joints, their ids are reused
+    /// for new particles. The hashes are rebuilt at the start of every update anyway.
+    fn remove_particles(&mut self, remove: impl Fn(&Particle) -> bool) {
+        let mut removed = vec![false; self.particles.len() + self.free_ids.len()];
+        let mut kept = 0;
+        for i in 0..self.particles.len() {
+            let particle = &self.particles[i];
+            if remove(particle) {
+                removed[particle.initial_id] = true;
+                self.free_ids.push(particle.initial_id);
+                continue;
             }
+            self.particles.swap(kept, i);
+            if i < self.previous_positions.len() {
+                self.previous_positions[kept] = self.previous_positions[i];
+            }
+            kept += 1;
+        }
+        if kept == self.particles.len() {
+            return;
+        }
+        self.removed_particles += (self.particles.len() - kept) as u64;
+        self.particles.truncate(kept);
+        self.previous_positions.truncate(kept);
+        let removed = |id: usize| removed[id];
+        self.joints.retain(|it| {
+            !removed(it.first) && !matches!(it.second, Anchor::Particle(id) if removed(id))
+        });
+        self.mouse.forget(removed);
+        self.update_indexes();
+        for cluster in &mut self.clusters {
+            cluster.remove_members(
+                removed,
+                &self.particles,
+                &self.indexes,
+                &self.scene.materials,
+            );
+        }
+        self.clusters.retain(|it| !it.is_empty());
+    }
+
+    fn push_particle(
+        &mut self,
+        position: Vec2,
+        velocity: Vec2,
+        radius: f32,
+        material: u32,
+        phase: u32,
+    ) {
+        let initial_id = self.free_ids.pop().unwrap_or(self.particles.len());
+        self.particles.push(Particle {
+            initial_id,
+            position,
+            radius,
+            velocity,
+            material,
+            body: 0,
+            phase,
+        });
+        if initial_id < self.expiry.len() {
+            self.expiry[initial_id] = f64::INFINITY;
+        } else {
+            self.expiry.resize(initial_id + 1, f64::INFINITY);
+        }
+        if initial_id >= self.colors.len() {
+            self.colors_changed = true;
+            self.colors.push(self.rng.get_random_color());
         }
     }
 
     fn update_particles(&mut self, dt: f32) {
         let len = self.particles.len();
-        let gravity = vec2(0.0, -30.00);
+        let gravity = self.scene.gravity;
+        let force_fields = &self.scene.force_fields;
+        let mouse_field = self.mouse.force_field(&self.scene.mouse);
         // let gravity = glam::vec2(0.0, -30.81)
         //     * if len < 34000 || len > 50000 && len < 69000 {
         //         -1.0
         //     } else {
         //         1.0
         //     };
-        let constraint = BoxConstraint::around_center(BOUND_RADIUS);
+        let constraint = self.scene.bounds;
+        let materials = &self.scene.materials;
+        let walls = &self.scene.walls;
+        let obstacles = &self.scene.obstacles;
+        let obstacle_grid = &self.obstacle_grid;
+        let time = self.time;
+        let restitution_threshold = restitution_threshold(gravity, dt);
         self.previous_positions.reserve(self.particles.len());
         // we will write to the whole length of this vec in the following code, without reading
         unsafe { self.previous_positions.set_len(self.particles.len()) };
 
-        let chunk_size = self.particles.len().div_ceil(NUM_THREADS);
+        let chunk_size = self.particles.len().div_ceil(self.num_threads).max(1);
         self.thread_pool.scope(|s| {
             self.particles
                 .chunks_mut(chunk_size)
@@ -291,9 +885,41 @@ impl Simulation {
                         particles.iter_mut().zip(previous_positions).for_each(
                             |(particle, previous_position)| {
                                 *previous_position = particle.position;
-                                particle.velocity += gravity * dt;
+                                particle.velocity += gravity * dt
+                                    + force_field::velocity_change(
+                                        force_fields.iter().chain(&mouse_field),
+                                        particle.position,
+                                        particle.velocity,
+                                        time as f32,
+                                        dt,
+                                    );
                                 particle.position += particle.velocity * dt;
-                                constraint.apply(particle, dt);
+                                let material = scene::material(materials, particle.material);
+                                constraint.apply(
+                                    particle,
+                                    previous_position,
+                                    material,
+                                    dt,
+                                    restitution_threshold,
+                                );
+                                for wall in walls {
+                                    wall.apply(
+                                        particle,
+                                        previous_position,
+                                        material,
+                                        dt,
+                                        restitution_threshold,
+                                    );
+                                }
+                                obstacle_grid.apply(
+                                    obstacles,
+                                    particle,
+                                    previous_position,
+                                    material,
+                                    time,
+                                    dt,
+                                    restitution_threshold,
+                                );
                             },
                         );
                     })
@@ -301,13 +927,12 @@ impl Simulation {
         });
     }
 
-    fn apply_distance_constraints(&mut self, dt: f32) {
+    fn apply_distance_constraints(&mut self, dt: f32, pass: Pass) {
         // let now = Instant::now();
         match self.collision_detection_mode {
-            0 => self.apply_stagger_threads(dt),
-            1 => self.apply_stagger_threads_mutex(dt),
-            _ => self.apply_sorted_threads(dt),
-            // // _ => self.apply_stagger_threads(dt),
+            CollisionMode::Stagger => self.apply_stagger_threads(dt, pass),
+            CollisionMode::RowChunks => self.apply_stagger_threads_mutex(dt, pass),
+            CollisionMode::Sorted => self.apply_sorted_threads(dt, pass),
         }
         // self.apply_stagger_threads(dt);
         // let elapsed = now.elapsed().as_secs_f64();
@@ -317,258 +942,115 @@ impl Simulation {
         //     .into();
     }
 
-    fn apply_stagger_threads(&mut self, dt: f32) {
-        const N: usize = NUM_THREADS;
-
+    // Processing row y touches rows y and y + 1, so all even rows can run at once and then all odd ones.
+    fn apply_stagger_threads(&mut self, dt: f32, pass: Pass) {
         let height = self.spatial_hash.grid().size().y;
+        let chunks = row_chunks(height, self.num_threads);
 
-        let chunk_size = height as usize / N;
+        let solver = self.solver(dt, pass);
 
-        let data_ptr = self.particles.as_mut_ptr() as u64;
-
-        let barrier = &Barrier::new(N);
+        let barrier = &Barrier::new(chunks.len());
         let spatial_hash = &self.spatial_hash;
         self.thread_pool.scope(|s| {
-            for n in 0..(N - 1) {
-                let start = n * chunk_size;
-                let end = (start + chunk_size) as u32;
-                let start1 = if start % 2 == 0 { start } else { start + 1 } as u32;
-                let start2 = if start % 2 == 0 { start + 1 } else { start } as u32;
+            for rows in chunks {
                 s.spawn(move |_| {
-                    Self::run_collision(
-                        (start1..end).step_by(2),
-                        spatial_hash,
-                        data_ptr as *mut Particle,
-                        dt,
-                    );
+                    let (even, odd) = split_by_parity(rows);
+                    Self::run_collision(even, spatial_hash, solver);
 
                     barrier.wait();
 
-                    Self::run_collision(
-                        (start2..end).step_by(2),
-                        spatial_hash,
-                        data_ptr as *mut Particle,
-                        dt,
-                    );
+                    Self::run_collision(odd, spatial_hash, solver);
                 });
             }
-
-            let start = (N - 1) * chunk_size;
-            let end = height;
-            let start1 = if start % 2 == 0 { start } else { start + 1 } as u32;
-            let start2 = if start % 2 == 0 { start + 1 } else { start } as u32;
-            Self::run_collision(
-                (start1..end).step_by(2),
-                spatial_hash,
-                data_ptr as *mut Particle,
-                dt,
-            );
-
-            barrier.wait();
-
-            Self::run_collision(
-                (start2..end).step_by(2),
-                spatial_hash,
-                data_ptr as *mut Particle,
-                dt,
-            );
         });
     }
-    fn apply_stagger_threads_mutex(&mut self, dt: f32) {
-        let height = self.spatial_hash.grid().size().y as usize;
-        let num_threads = NUM_THREADS.min(height / 4);
-
-        let even_height = height & !1;
-        let even_chunk_size = (even_height / num_threads) & !1;
-
-        // always overflow < num_threads * 2 and is even
-        let overflow = even_height - even_chunk_size * num_threads;
-
-        // always < num_threads and divides evenly
-        let num_chunks_to_increase = overflow / 2; // some chunks will be increased by 2
 
-        let small_chunk_size = even_chunk_size;
-        let big_chunk_suze = even_chunk_size + 2;
-
-        let overlaps = (1..num_chunks_to_increase)
-            .map(|i| (i * big_chunk_suze) as u32)
-            .chain(
-                ((num_chunks_to_increase)..num_threads)
-                    .map(|i| (i * small_chunk_size + num_chunks_to_increase * 2) as u32),
-            )
-            .map(|start| (start, AtomicBool::new(false)))
+    // Each thread owns a chunk of rows, processing row y touches rows y and y + 1, so only
+    // the last row of a chunk and the first row of the next one ever touch the same particles.
+    // The first row of every chunk goes first and the chunk below waits for it before its
+    // last row, which keeps the order fixed and runs reproducible.
+    fn apply_stagger_threads_mutex(&mut self, dt: f32, pass: Pass) {
+        let height = self.spatial_hash.grid().size().y;
+        // every chunk needs a first and a last row of its own
+        let chunks = row_chunks(height, self.num_threads.min(height as usize / 2));
+        let first_row_done = (0..chunks.len())
+            .map(|_| AtomicBool::new(false))
             .collect::<Vec<_>>();
 
+        let solver = self.solver(dt, pass);
         let spatial_hash = &self.spatial_hash;
-        let UVec2 {
-            x: width,
-            y: height,
-        } = spatial_hash.grid().size();
-
-        let data_ptr = self.particles.as_mut_ptr() as u64;
-        let barrier = &Barrier::new(num_threads);
         self.thread_pool.scope(|s| {
-            s.spawn(|_| {
-                let (start, start_mut) = overlaps.last().unwrap();
-                Self::run_mutex_collision(
-                    barrier,
-                    *start,
-                    height,
-                    Some(start_mut),
-                    None,
-                    spatial_hash,
-                    data_ptr as *mut Particle,
-                    dt,
-                );
-            });
-            for it in overlaps.windows(2) {
-                s.spawn(|_| {
-                    let (start, start_mut) = &it[0];
-                    let (end, end_mut) = &it[1];
+            for (n, rows) in chunks.into_iter().enumerate() {
+                let first_row_done = &first_row_done;
+                s.spawn(move |_| {
                     Self::run_mutex_collision(
-                        barrier,
-                        *start,
-                        *end,
-                        Some(start_mut),
-                        Some(end_mut),
+                        rows.start,
+                        rows.end,
+                        &first_row_done[n],
+                        first_row_done.get(n + 1),
                         spatial_hash,
-                        data_ptr as *mut Particle,
-                        dt,
+                        solver,
                     );
                 });
             }
-            s.spawn(|_| {
-                let (end, end_mut) = &overlaps[0];
-                Self::run_mutex_collision(
-                    barrier,
-                    0,
-                    *end,
-                    None,
-                    Some(end_mut),
-                    spatial_hash,
-                    data_ptr as *mut Particle,
-                    dt,
-                );
-            });
-            // for n in 0..(N - 1) {
-            //     let start = n * chunk_size;
-            //     let end = (start + chunk_size) as u32;
-            //     let start1 = if start % 2 == 0 { start } else { start + 1 } as u32;
-            //     let start2 = if start % 2 == 0 { start + 1 } else { start } as u32;
-            //     s.spawn(move |_| {
-            //         Self::run_collision(
-            //             (start1..end).step_by(2),
-            //             spatial_hash,
-            //             data_ptr as *mut Particle,
-            //             dt,
-            //         );
-            //
-            //         barrier.wait();
-            //
-            //         Self::run_collision(
-            //             (start2..end).step_by(2),
-            //             spatial_hash,
-            //             data_ptr as *mut Particle,
-            //             dt,
-            //         );
-            //     });
-            // }
-            //
-            // let start = (N - 1) * chunk_size;
-            // let end = height;
-            // let start1 = if start % 2 == 0 { start } else { start + 1 } as u32;
-            // let start2 = if start % 2 == 0 { start + 1 } else { start } as u32;
-            // Self::run_collision(
-            //     (start1..end).step_by(2),
-            //     spatial_hash,
-            //     data_ptr as *mut Particle,
-            //     dt,
-            // );
-            //
-            // barrier.wait();
-            //
-            // Self::run_collision(
-            //     (start2..end).step_by(2),
-            //     spatial_hash,
-            //     data_ptr as *mut Particle,
-            //     dt,
-            // );
         });
     }
 
-    fn apply_sorted_threads(&mut self, dt: f32) {
-        const N: usize = NUM_THREADS;
-
-        let height = self.spatial_hash.grid().size().y;
+    fn apply_sorted_threads(&mut self, dt: f32, pass: Pass) {
+        let height = self.sorting_hash.grid().size().y;
+        let chunks = row_chunks(height, self.num_threads);
 
-        let chunk_size = height as usize / N;
+        let solver = self.solver(dt, pass);
 
-        let data_ptr = self.particles.as_mut_ptr() as u64;
-
-        let barrier = &Barrier::new(N);
+        let barrier = &Barrier::new(chunks.len());
         let spatial_hash = &self.sorting_hash;
         self.thread_pool.scope(|s| {
-            for n in 0..(N - 1) {
-                let start = n * chunk_size;
-                let end = (start + chunk_size) as u32;
-                let start1 = if start % 2 == 0 { start } else { start + 1 } as u32;
-                let start2 = if start % 2 == 0 { start + 1 } else { start } as u32;
+            for rows in chunks {
                 s.spawn(move |_| {
-                    Self::run_sorted_collision(
-                        (start1..end).step_by(2),
-                        spatial_hash,
-                        data_ptr as *mut Particle,
-                        dt,
-                    );
+                    let (even, odd) = split_by_parity(rows);
+                    Self::run_sorted_collision(even, spatial_hash, solver);
 
                     barrier.wait();
 
-                    Self::run_sorted_collision(
-                        (start2..end).step_by(2),
-                        spatial_hash,
-                        data_ptr as *mut Particle,
-                        dt,
-                    );
+                    Self::run_sorted_collision(odd, spatial_hash, solver);
                 });
             }
-
-            let start = (N - 1) * chunk_size;
-            let end = height;
-            let start1 = if start % 2 == 0 { start } else { start + 1 } as u32;
-            let start2 = if start % 2 == 0 { start + 1 } else { start } as u32;
-            Self::run_sorted_collision(
-                (start1..end).step_by(2),
-                spatial_hash,
-                data_ptr as *mut Particle,
-                dt,
-            );
-
-            barrier.wait();
-
-            Self::run_sorted_collision(
-                (start2..end).step_by(2),
-                spatial_hash,
-                data_ptr as *mut Particle,
-                dt,
-            );
         });
     }
 
+    fn solver(&mut self, dt: f32, pass: Pass) -> Solver {
+        Solver {
+            particles: self.particles.as_mut_ptr(),
+            previous_positions: self.previous_positions.as_ptr(),
+            previous_velocities: self.previous_velocities.as_ptr(),
+            row_contacts: self.row_contacts.as_mut_ptr(),
+            materials: self.scene.materials.as_slice(),
+            interactions: &self.interactions,
+            dt,
+            track_lambdas: self.scene.iterations > 1,
+            restitution_threshold: restitution_threshold(self.scene.gravity, dt),
+            pass,
+        }
+    }
+
     pub fn on_image_loaded(&mut self, img: image::DynamicImage) {
+        let bounds = self.scene.bounds;
+        let bounds_size = vec2(bounds.right - bounds.left, bounds.top - bounds.bottom);
         let (width, height) = img.dimensions();
         let width = width as f32;
         let height = height as f32;
-        let dim = width.max(height) as f32;
+        let dim = width.max(height);
         let offset = vec2(
-            ((dim - width as f32) / 2.0).min(0.0),
-            ((dim - height as f32) / 2.0).min(0.0),
+            ((dim - width) / 2.0).min(0.0),
+            ((dim - height) / 2.0).min(0.0),
         );
         for i in 0..self.particles.len() {
             let particle = &self.particles[i];
-            let pos = (vec2(particle.position.x, -particle.position.y)
-                + vec2(BOUND_RADIUS, BOUND_RADIUS))
-                * dim
-                / (BOUND_RADIUS * 2.0);
+            let pos = vec2(
+                particle.position.x - bounds.left,
+                bounds.top - particle.position.y,
+            ) * dim
+                / bounds_size.max_element();
             if pos.x < offset.x
                 || pos.x > offset.x + width
                 || pos.y < offset.y
@@ -595,82 +1077,57 @@ impl Simulation {
         self.colors_changed = true;
     }
     fn run_mutex_collision(
-        barrier: &Barrier,
-        start: u32, //always even
-        end: u32,   // even if end mutex exists otherwice == height
-        start_mut: Option<&AtomicBool>,
-        end_mut: Option<&AtomicBool>,
+        start: u32,
+        end: u32,
+        start_done: &AtomicBool,
+        end_done: Option<&AtomicBool>,
         spatial_hash: &PointerHash<FixedSizeGrid>,
-        data_ptr: *mut Particle,
-        dt: f32,
+        solver: Solver,
     ) {
-        // let UVec2 {
-        //     x: width,
-        //     y: height,
-        // } = spatial_hash.grid().size();
-        // {
-        //     let _guard = start_mut.map(|it| it.store(val, order));
-        //     barrier.wait();
-        //     Self::run_row_collision(start, width, height, spatial_hash, data_ptr, dt);
-        // }
-        //
-        // for y in ((start + 1)..(end - 1)).step_by(2) {
-        //     Self::run_row_collision(
-        //         y + 1,
-        //         width,
-        //         height,
-        //         spatial_hash,
-        //         data_ptr as *mut Particle,
-        //         dt,
-        //     );
-        //     Self::run_row_collision(
-        //         y,
-        //         width,
-        //         height,
-        //         spatial_hash,
-        //         data_ptr as *mut Particle,
-        //         dt,
-        //     );
-        // }
-        //
-        // if end & 1 == 0 {
-        //     let _guard = end_mut.map(|it| it.lock());
-        //     Self::run_row_collision(
-        //         end - 1,
-        //         width,
-        //         height,
-        //         spatial_hash,
-        //         data_ptr as *mut Particle,
-        //         dt,
-        //     )
-        // }
+        let UVec2 {
+            x: width,
+            y: height,
+        } = spatial_hash.grid().size();
+
+        Self::run_row_collision(start, width, height, spatial_hash, solver);
+        start_done.store(true, Ordering::Release);
+
+        if end - start < 2 {
+            return;
+        }
+        for y in (start + 1)..(end - 1) {
+            Self::run_row_collision(y, width, height, spatial_hash, solver);
+        }
+
+        if let Some(end_done) = end_done {
+            while !end_done.load(Ordering::Acquire) {
+                std::hint::spin_loop();
+            }
+        }
+        Self::run_row_collision(end - 1, width, height, spatial_hash, solver);
     }
 
     fn run_sorted_collision(
-        y: std::iter::StepBy<std::ops::Range<u32>>,
+        y: StepBy<Range<u32>>,
         spatial_hash: &SortingHash<FixedSizeGrid>,
-        data_ptr: *mut Particle,
-        dt: f32,
+        solver: Solver,
     ) {
         let UVec2 {
             x: width,
             y: height,
         } = spatial_hash.grid().size();
         for y in y {
+            let contacts = unsafe { solver.row_contacts(y) };
             for x in 0..width {
                 let (this_start, this_end) = spatial_hash.get_pointers(x, y);
                 for i in this_start..this_end {
                     for j in (i + 1)..this_end {
-                        let first = unsafe { &mut *(data_ptr.add(i)) };
-                        let second = unsafe { &mut *(data_ptr.add(j)) };
-                        apply_distance_constraint(first, second, dt);
+                        solver.solve(contacts, i, j);
                     }
                     if x < width - 1 {
                         let (other_start, other_end) = spatial_hash.get_pointers(x + 1, y);
                         for j in other_start..other_end {
-                            let first = unsafe { &mut *(data_ptr.add(i)) };
-                            let second = unsafe { &mut *(data_ptr.add(j)) };
-                            apply_distance_constraint(first, second, dt);
+                            solver.solve(contacts, i, j);
                         }
                     }
                     if y < height - 1 {
@@ -680,9 +1137,7 @@ impl Simulation {
                             y + 1,
                         );
                         for j in other_start..other_end {
-                            let first = unsafe { &mut *(data_ptr.add(i)) };
-                            let second = unsafe { &mut *(data_ptr.add(j)) };
-                            apply_distance_constraint(first, second, dt);
+                            solver.solve(contacts, i, j);
                         }
                     }
                 }
@@ -718,17 +1173,16 @@ impl Simulation {
     }
 
     fn run_collision(
-        y: std::iter::StepBy<std::ops::Range<u32>>,
+        y: StepBy<Range<u32>>,
         spatial_hash: &PointerHash<FixedSizeGrid>,
-        data_ptr: *mut Particle,
-        dt: f32,
+        solver: Solver,
     ) {
         let UVec2 {
             x: width,
             y: height,
         } = spatial_hash.grid().size();
         for y in y {
-            Self::run_row_collision(y, width, height, spatial_hash, data_ptr, dt);
+            Self::run_row_collision(y, width, height, spatial_hash, solver);
         }
     }
 
@@ -737,9 +1191,9 @@ impl Simulation {
         width: u32,
         height: u32,
         spatial_hash: &PointerHash<FixedSizeGrid>,
-        data_ptr: *mut Particle,
-        dt: f32,
+        solver: Solver,
     ) {
+        let contacts = unsafe { solver.row_contacts(y) };
         for x in 0..width {
             let indicies = spatial_hash.get_indexes_by_cell(uvec2(x, y));
             let other_indicies = []
@@ -766,27 +1220,155 @@ impl Simulation {
                 })
                 .flat_map(|(xx, yy)| spatial_hash.get_indexes_by_cell(uvec2(xx, yy)));
 
-            Self::run_cell_collisions(indicies, other_indicies, data_ptr, dt);
+            Self::run_cell_collisions(indicies, other_indicies, contacts, solver);
         }
     }
 
     fn run_cell_collisions<'a, I: Iterator<Item = &'a usize> + Clone>(
         indicies: &'a [usize],
         other_indicies: I,
-        data_ptr: *mut Particle,
-        dt: f32,
+        contacts: &mut RowContacts,
+        solver: Solver,
     ) {
         for (i, first_index) in indicies.iter().enumerate() {
             for second_index in indicies.iter().skip(i + 1).chain(other_indicies.clone()) {
-                let first = unsafe { &mut *(data_ptr.add(*first_index)) };
-                let second = unsafe { &mut *(data_ptr.add(*second_index)) };
-                apply_distance_constraint(first, second, dt);
+                solver.solve(contacts, *first_index, *second_index);
             }
         }
     }
 }
 
-fn apply_distance_constraint(first: &mut Particle, second: &mut Particle, dt: f32) {
+/// Splits `0..height` into at most `num_threads` contiguous chunks whose sizes differ by at most one row,
+/// never returning an empty chunk unless the grid itself is empty.
+fn row_chunks(height: u32, num_threads: usize) -> Vec<Range<u32>> {
+    let num_chunks = num_threads.min(height as usize).max(1);
+    (0..num_chunks)
+        .map(|i| {
+            let start = (i * height as usize / num_chunks) as u32;
+            let end = ((i + 1) * height as usize / num_chunks) as u32;
+            start..end
+        })
+        .collect()
+}
+
+fn split_by_parity(rows: Range<u32>) -> (StepBy<Range<u32>>, StepBy<Range<u32>>) {
+    let even_start = rows.start + rows.start % 2;
+    let odd_start = rows.start + 1 - rows.start % 2;
+    (
+        (even_start..rows.end).step_by(2),
+        (odd_start..rows.end).step_by(2),
+    )
+}
+
+impl RowContacts {
+    fn clear(&mut self) {
+        self.lambdas.clear();
+        self.next = 0;
+    }
+
+    fn rewind(&mut self) {
+        self.next = 0;
+    }
+
+    fn next_lambda(&mut self) -> &mut f32 {
+        if self.next == self.lambdas.len() {
+            self.lambdas.push(0.0);
+        }
+        self.next += 1;
+        &mut self.lambdas[self.next - 1]
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Pass {
+    /// Pushes overlapping grains apart, with friction
+    Positions,
+    /// Restores the bounce of colliding grains after velocities were derived from positions
+    Velocities,
+}
+
+/// Shared by the collision workers. Rows are handed out so that no two threads touch
+/// the same particles or the same row contacts at once.
+#[derive(Clone, Copy)]
+struct Solver {
+    particles: *mut Particle,
+    previous_positions: *const Vec2,
+    previous_velocities: *const Vec2,
+    row_contacts: *mut RowContacts,
+    materials: *const [Material],
+    interactions: *const Interactions,
+    dt: f32,
+    // with a single iteration every multiplier starts and ends at 0, no need to store them
+    track_lambdas: bool,
+    restitution_threshold: f32,
+    pass: Pass,
+}
+
+unsafe impl Send for Solver {}
+unsafe impl Sync for Solver {}
+
+impl Solver {
+    /// Only the thread processing row `y` may hold its contacts.
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn row_contacts(&self, y: u32) -> &mut RowContacts {
+        &mut *self.row_contacts.add(y as usize)
+    }
+
+    fn solve(&self, contacts: &mut RowContacts, i: usize, j: usize) {
+        let first = unsafe { &mut *self.particles.add(i) };
+        let second = unsafe { &mut *self.particles.add(j) };
+        let materials = unsafe { &*self.materials };
+        let interactions = unsafe { &*self.interactions };
+        // the same pairs are skipped in every iteration, so the multipliers stay in step
+        if first.body != 0 && first.body == second.body
+            || interactions.rule(first, second, materials) != Rule::Contact
+        {
+            return;
+        }
+        match self.pass {
+            Pass::Positions => {
+                let mut lambda = 0.0;
+                let lambda = if self.track_lambdas {
+                    contacts.next_lambda()
+                } else {
+                    &mut lambda
+                };
+                let previous = unsafe {
+                    (
+                        *self.previous_positions.add(i),
+                        *self.previous_positions.add(j),
+                    )
+                };
+                apply_distance_constraint(first, second, previous, materials, lambda, self.dt);
+            }
+            Pass::Velocities => {
+                let velocities_before = unsafe {
+                    (
+                        *self.previous_velocities.add(i),
+                        *self.previous_velocities.add(j),
+                    )
+                };
+                apply_contact_restitution(
+                    first,
+                    second,
+                    velocities_before,
+                    materials,
+                    self.restitution_threshold,
+                );
+            }
+        }
+    }
+}
+
+// XPBD, `lambda` is the multiplier accumulated for this contact over the iterations of a substep.
+fn apply_distance_constraint(
+    first: &mut Particle,
+    second: &mut Particle,
+    (first_previous, second_previous): (Vec2, Vec2),
+    materials: &[Material],
+    lambda: &mut f32,
+    dt: f32,
+) {
     // let v = first.position - second.position;
     // let dist = v.length();
     // let min_dist = first.radius + second.radius;
@@ -798,27 +1380,115 @@ fn apply_distance_constraint(first: &mut Particle, second: &mut Particle, dt: f3
     // }
     // let alpha = 0.000004337 * 2.0;
     // let alpha = 1e-4;
-    let alpha = 0.0;
     let vector = second.position - first.position;
+    let constraint = vector.length() - first.radius - second.radius;
+    let first_material = scene::material(materials, first.material);
+    let second_material = scene::material(materials, second.material);
+    let cohesion = (first_material.cohesion + second_material.cohesion) * 0.5;
+    let range = if cohesion > 0.0 {
+        (first_material.cohesion_range + second_material.cohesion_range) * 0.5
+    } else {
+        0.0
+    };
+    if constraint >= range && *lambda == 0.0 {
+        return;
+    }
     let direction = vector.normalize_or(vec2(1.0, 0.0));
-    let lambda = (vector.length() - first.radius - second.radius) / (2.0 + alpha / (dt * dt));
-    if lambda >= 0.0 {
+    let first_weight = first_material.inverse_mass(first.radius);
+    let second_weight = second_material.inverse_mass(second.radius);
+    let weight = first_weight + second_weight;
+    let alpha = (first_material.compliance + second_material.compliance) / (dt * dt);
+    // contacts push, cohesion pulls with at most its force and only within range
+    let min_lambda = if constraint < range {
+        -cohesion * dt * dt
+    } else {
+        0.0
+    };
+    let delta_lambda =
+        ((-constraint - alpha * *lambda) / (weight + alpha)).max(min_lambda - *lambda);
+    if delta_lambda == 0.0 {
         return;
     }
-    let correction = direction * lambda;
-    first.position += correction;
-    second.position -= correction;
+    *lambda += delta_lambda;
+    let correction = direction * delta_lambda;
+    first.position -= correction * first_weight;
+    second.position += correction * second_weight;
+
+    if delta_lambda > 0.0 {
+        let relative = (second.position - second_previous) - (first.position - first_previous);
+        let slip = relative - direction * relative.dot(direction);
+        let penetration = delta_lambda * weight;
+        let correction = friction(slip, penetration, first_material, second_material);
+        first.position -= correction * (first_weight / weight);
+        second.position += correction * (second_weight / weight);
+    }
+}
+
+fn apply_contact_restitution(
+    first: &mut Particle,
+    second: &mut Particle,
+    (first_before, second_before): (Vec2, Vec2),
+    materials: &[Material],
+    threshold: f32,
+) {
+    let vector = second.position - first.position;
+    if vector.length() > first.radius + second.radius + 1e-3 {
+        return;
+    }
+    let direction = vector.normalize_or(vec2(1.0, 0.0));
+    let approach_before = (second_before - first_before).dot(direction);
+    if approach_before > -threshold {
+        return;
+    }
+    let first_material = scene::material(materials, first.material);
+    let second_material = scene::material(materials, second.material);
+    let restitution = (first_material.restitution + second_material.restitution) * 0.5;
+    let approach = (second.velocity - first.velocity).dot(direction);
+    let change = -approach_before * restitution - approach;
+    let first_weight = first_material.inverse_mass(first.radius);
+    let second_weight = second_material.inverse_mass(second.radius);
+    let weight = first_weight + second_weight;
+    first.velocity -= direction * (change * first_weight / weight);
+    second.velocity += direction * (change * second_weight / weight);
+}
+
+fn neighbourhood<'a>(
+    mode: CollisionMode,
+    spatial_hash: &'a PointerHash<FixedSizeGrid>,
+    sorting_hash: &'a SortingHash<FixedSizeGrid>,
+) -> Neighbourhood<'a, FixedSizeGrid> {
+    match mode {
+        CollisionMode::Stagger | CollisionMode::RowChunks => Neighbourhood::Pointer(spatial_hash),
+        CollisionMode::Sorted => Neighbourhood::Sorting(sorting_hash),
+    }
+}
+
+// Bounces slower than what gravity adds in two substeps would only jitter.
+fn restitution_threshold(gravity: Vec2, dt: f32) -> f32 {
+    2.0 * gravity.length() * dt
+}
+
+/// The correction friction applies to a tangential `slip` at a contact `penetration` deep,
+/// coefficients are averaged over both materials.
+fn friction(slip: Vec2, penetration: f32, first: &Material, second: &Material) -> Vec2 {
+    let static_friction = (first.static_friction + second.static_friction) * 0.5;
+    let dynamic_friction = (first.dynamic_friction + second.dynamic_friction) * 0.5;
+    let length = slip.length();
+    if length <= static_friction * penetration {
+        return -slip;
+    }
+    -slip * (dynamic_friction * penetration / length).min(1.0)
 }
 
 trait MyRng {
-    fn get_random_size(&mut self) -> f32;
+    fn get_random_size(&mut self, range: (f32, f32)) -> f32;
 
     fn get_random_color(&mut self) -> Color;
 }
 
-impl MyRng for StdRng {
-    fn get_random_size(&mut self) -> f32 {
-        self.gen_range(1.0..=1.0) * MAX_PARTICLE_RADIUS
+impl MyRng for ChaCha12Rng {
+    fn get_random_size(&mut self, (min, max): (f32, f32)) -> f32 {
+        self.gen_range(min..=max)
     }
 
     fn get_random_color(&mut self) -> Color {
@@ -922,3 +1592,60 @@ impl Positioned for Particle {
         self.position
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{row_chunks, split_by_parity};
+
+    fn assert_partitions(height: u32, num_threads: usize) {
+        let chunks = row_chunks(height, num_threads);
+        assert!(!chunks.is_empty() && chunks.len() <= num_threads.max(1));
+        assert_eq!(chunks.first().unwrap().start, 0);
+        assert_eq!(chunks.last().unwrap().end, height);
+        // contiguous, so every row is covered exactly once
+        for pair in chunks.windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+        if height > 0 {
+            let sizes = chunks.iter().map(|it| it.len()).collect::<Vec<_>>();
+            assert!(sizes.iter().all(|it| *it > 0));
+            assert!(sizes.iter().max().unwrap() - sizes.iter().min().unwrap() <= 1);
+        }
+    }
+
+    #[test]
+    fn row_chunks_cover_every_row_once() {
+        for height in [0, 1, 2, 3, 7, 8, 9, 63, 64, 101] {
+            for num_threads in [0, 1, 2, 3, 4, 16, 200] {
+                assert_partitions(height, num_threads);
+            }
+        }
+    }
+
+    #[test]
+    fn row_chunks_edge_cases() {
+        // a single chunk, empty only when the grid is
+        for (height, expected) in [(0, 0..0), (1, 0..1)] {
+            let chunks = row_chunks(height, 4);
+            assert_eq!(chunks.len(), 1);
+            assert_eq!(chunks[0], expected);
+        }
+        assert_eq!(row_chunks(3, 8), [0..1, 1..2, 2..3]);
+        assert_eq!(row_chunks(7, 3), [0..2, 2..4, 4..7]);
+    }
+
+    #[test]
+    fn split_by_parity_separates_rows() {
+        for start in 0..4 {
+            for end in start..start + 6 {
+                let (even, odd) = split_by_parity(start..end);
+                let (even, odd) = (even.collect::<Vec<_>>(), odd.collect::<Vec<_>>());
+                assert!(even.iter().all(|it| it % 2 == 0));
+                assert!(odd.iter().all(|it| it % 2 == 1));
+                let mut all = even.into_iter().chain(odd).collect::<Vec<_>>();
+                all.sort();
+                assert_eq!(all, (start..end).collect::<Vec<_>>());
+            }
+        }
+    }
+}
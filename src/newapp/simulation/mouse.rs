@@ -0,0 +1,123 @@
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+use super::{force_field::ForceField, Particle};
+
+/// What holding the mouse button does to the particles under the cursor.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Tool {
+    /// Drags them along with the cursor, they keep its velocity when released
+    #[default]
+    Grab,
+    Push,
+    Attract,
+    Delete,
+    /// Spawns new particles
+    Paint,
+}
+
+/// How the mouse tools act on the particles.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct MouseTools {
+    /// Tools reach the particles within this distance of the cursor
+    pub radius: f32,
+    /// Acceleration of push and attract at the cursor, fading out at `radius`
+    pub strength: f32,
+    /// Particles painted per update while the button is held
+    pub paint_rate: u32,
+    /// Counts towards the scene's largest radius, which sizes the grid cells
+    pub paint_radius: (f32, f32),
+    pub paint_material: u32,
+    pub paint_phase: u32,
+}
+
+/// The cursor in world coordinates and the tool it holds.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Mouse {
+    pub position: Vec2,
+    /// Position at the end of the last update, grabbed particles follow the cursor in between
+    pub last_position: Vec2,
+    pub pressed: bool,
+    pub tool: Tool,
+    // initial ids of the grabbed particles and their offsets from the cursor
+    grabbed: Vec<(usize, Vec2)>,
+}
+
+impl Tool {
+    pub const ALL: [Tool; 5] = [
+        Tool::Grab,
+        Tool::Push,
+        Tool::Attract,
+        Tool::Delete,
+        Tool::Paint,
+    ];
+}
+
+impl Default for MouseTools {
+    fn default() -> Self {
+        Self {
+            radius: 10.0,
+            strength: 300.0,
+            paint_rate: 4,
+            paint_radius: (1.0, 1.0),
+            paint_material: 0,
+            paint_phase: 0,
+        }
+    }
+}
+
+impl Mouse {
+    pub fn is_using(&self, tool: Tool) -> bool {
+        self.pressed && self.tool == tool
+    }
+
+    /// Called once the update that saw the cursor at `position` is done.
+    pub fn moved(&mut self) {
+        self.last_position = self.position;
+    }
+
+    /// Push and attract are radial force fields around the cursor.
+    pub fn force_field(&self, tools: &MouseTools) -> Option<ForceField> {
+        let strength = match self.tool {
+            Tool::Push if self.pressed => -tools.strength,
+            Tool::Attract if self.pressed => tools.strength,
+            _ => return None,
+        };
+        Some(ForceField::Radial {
+            center: self.position,
+            radius: tools.radius,
+            strength,
+        })
+    }
+
+    pub(super) fn is_grabbing(&self) -> bool {
+        !self.grabbed.is_empty()
+    }
+
+    pub(super) fn grab(&mut self, particles: &[Particle], tools: &MouseTools) {
+        self.last_position = self.position;
+        self.grabbed = particles
+            .iter()
+            .filter(|it| it.position.distance(self.position) < tools.radius)
+            .map(|it| (it.initial_id, it.position - self.position))
+            .collect();
+    }
+
+    pub(super) fn release(&mut self) {
+        self.grabbed.clear();
+    }
+
+    /// Drops the grabbed particles matching `removed`.
+    pub(super) fn forget(&mut self, removed: impl Fn(usize) -> bool) {
+        self.grabbed.retain(|(id, _)| !removed(*id));
+    }
+
+    /// Moves the grabbed particles to where the cursor is `progress` of the way through the update.
+    pub(super) fn hold(&self, particles: &mut [Particle], indexes: &[usize], progress: f32) {
+        let cursor = self.last_position.lerp(self.position, progress);
+        for &(id, offset) in &self.grabbed {
+            particles[indexes[id]].position = cursor + offset;
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::f64::consts::TAU;
+
+use glam::{uvec2, Vec2};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    boundary::{closest_on_segment, collide},
+    scene::Material,
+    spatial_hash::SpatialGrid,
+    Particle,
+};
+
+/// Scripted movement around the obstacle's position, `time` in seconds since the start.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum Motion {
+    #[default]
+    Static,
+    /// Radians per second, counter-clockwise
+    Rotate { angular_velocity: f32 },
+    /// `amplitude * sin(TAU * time / period)`
+    Oscillate { amplitude: Vec2, period: f32 },
+}
+
+/// Thin segments between consecutive `points`, given relative to `position`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default)]
+pub struct Obstacle {
+    pub position: Vec2,
+    pub points: Vec<Vec2>,
+    /// Also joins the last point to the first one
+    pub closed: bool,
+    /// Half the thickness of the segments, fast particles can tunnel through thin ones
+    pub radius: f32,
+    pub motion: Motion,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    start: Vec2,
+    end: Vec2,
+    radius: f32,
+    obstacle: u32,
+}
+
+/// World space segments of all obstacles, binned into every cell a touching particle can be in.
+pub struct ObstacleGrid<Grid: SpatialGrid> {
+    grid: Grid,
+    segments: Vec<Segment>,
+    pointers: Vec<usize>,
+    indexes: Vec<u32>,
+    // static obstacles only need to be binned once
+    built: bool,
+}
+
+impl Motion {
+    // offset and rotation, as a unit vector
+    fn pose(self, time: f64) -> (Vec2, Vec2) {
+        match self {
+            Motion::Static => (Vec2::ZERO, Vec2::X),
+            Motion::Rotate { angular_velocity } => (
+                Vec2::ZERO,
+                Vec2::from_angle((angular_velocity as f64 * time) as f32),
+            ),
+            Motion::Oscillate { amplitude, period } => (
+                amplitude * (TAU * time / period as f64).sin() as f32,
+                Vec2::X,
+            ),
+        }
+    }
+
+    /// Velocity of the point at `offset` from the obstacle's current position.
+    fn velocity(self, offset: Vec2, time: f64) -> Vec2 {
+        match self {
+            Motion::Static => Vec2::ZERO,
+            Motion::Rotate { angular_velocity } => offset.perp() * angular_velocity,
+            Motion::Oscillate { amplitude, period } => {
+                let frequency = TAU / period as f64;
+                amplitude * (frequency * (frequency * time).cos()) as f32
+            }
+        }
+    }
+}
+
+impl Obstacle {
+    fn moves(&self) -> bool {
+        self.motion != Motion::Static
+    }
+
+    fn world_points(&self, time: f64) -> impl Iterator<Item = Vec2> + '_ {
+        let (offset, rotation) = self.motion.pose(time);
+        let position = self.position + offset;
+        self.points
+            .iter()
+            .map(move |it| position + rotation.rotate(*it))
+    }
+
+    fn center(&self, time: f64) -> Vec2 {
+        self.position + self.motion.pose(time).0
+    }
+}
+
+impl<Grid: SpatialGrid> ObstacleGrid<Grid> {
+    pub fn new(grid: Grid) -> Self {
+        let n_cells = grid.number_of_cells();
+        Self {
+            grid,
+            segments: vec![],
+            pointers: vec![0; n_cells + 1],
+            indexes: vec![],
+            built: false,
+        }
+    }
+
+    /// Places the obstacles at `time`, `margin` is the largest particle radius.
+    pub fn build(&mut self, obstacles: &[Obstacle], time: f64, margin: f32) {
+        if self.built && !obstacles.iter().any(Obstacle::moves) {
+            return;
+        }
+        self.built = true;
+        self.segments.clear();
+        for (i, obstacle) in obstacles.iter().enumerate() {
+            let points = obstacle.world_points(time).collect::<Vec<_>>();
+            let closing = if obstacle.closed && points.len() > 2 {
+                Some((points[points.len() - 1], points[0]))
+            } else {
+                None
+            };
+            let segments = points
+                .windows(2)
+                .map(|it| (it[0], it[1]))
+                .chain(closing)
+                .map(|(start, end)| Segment {
+                    start,
+                    end,
+                    radius: obstacle.radius,
+                    obstacle: i as u32,
+                });
+            self.segments.extend(segments);
+        }
+
+        // same counting sort as the pointer hash, except that a segment covers several cells
+        self.pointers.fill(0);
+        for segment in &self.segments {
+            for cell in cells(&self.grid, segment, margin) {
+                self.pointers[cell] += 1;
+            }
+        }
+        let mut sum = 0;
+        for pointer in &mut self.pointers {
+            sum += *pointer;
+            *pointer = sum;
+        }
+        self.indexes.resize(sum, 0);
+        for (index, segment) in self.segments.iter().enumerate() {
+            for cell in cells(&self.grid, segment, margin) {
+                self.pointers[cell] -= 1;
+                self.indexes[self.pointers[cell]] = index as u32;
+            }
+        }
+    }
+
+    /// Pushes the particle out of every segment it overlaps.
+    #[allow(clippy::too_many_arguments)]
+    pub fn apply(
+        &self,
+        obstacles: &[Obstacle],
+        particle: &mut Particle,
+        previous_position: &mut Vec2,
+        material: &Material,
+        time: f64,
+        dt: f32,
+        threshold: f32,
+    ) {
+        let cell = self.grid.get_position_cell_index(particle.position);
+        for &index in &self.indexes[self.pointers[cell]..self.pointers[cell + 1]] {
+            let segment = self.segments[index as usize];
+            let closest = closest_on_segment(segment.start, segment.end, particle.position);
+            let offset = particle.position - closest;
+            let distance = offset.length();
+            let penetration = segment.radius + particle.radius - distance;
+            if penetration <= -material.cohesion_range
+                || penetration <= 0.0 && material.adhesion <= 0.0
+            {
+                continue;
+            }
+            // a particle centered on the segment gets pushed to its left
+            let normal =
+                offset.normalize_or((segment.end - segment.start).perp().normalize_or(Vec2::Y));
+            let obstacle = &obstacles[segment.obstacle as usize];
+            let surface_velocity = obstacle
+                .motion
+                .velocity(closest - obstacle.center(time), time);
+            collide(
+                particle,
+                previous_position,
+                (penetration, normal),
+                surface_velocity,
+                material,
+                dt,
+                threshold,
+            );
+        }
+    }
+}
+
+fn cells<'a>(
+    grid: &'a impl SpatialGrid,
+    segment: &Segment,
+    margin: f32,
+) -> impl Iterator<Item = usize> + 'a {
+    let reach = Vec2::splat(segment.radius + margin);
+    let min = grid.get_cell_coords(segment.start.min(segment.end) - reach);
+    let max = grid.get_cell_coords(segment.start.max(segment.end) + reach);
+    (min.y..=max.y)
+        .flat_map(move |y| (min.x..=max.x).map(move |x| grid.get_cell_index(uvec2(x, y))))
+}
@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+
+use super::{
+    scene::{self, Material},
+    Particle,
+};
+
+/// Overrides how particles of two phases treat each other, in either order.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Interaction {
+    pub phases: (u32, u32),
+    pub rule: Rule,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rule {
+    #[default]
+    Contact,
+    /// Pass through each other
+    Ignore,
+    /// Density constraints instead of contacts, pairs that aren't both fluids ignore each other
+    Fluid,
+}
+
+/// The scene's interactions as a table. Pairs without a rule collide, unless both are fluids.
+#[derive(Debug, Clone, Default)]
+pub struct Interactions {
+    phases: usize,
+    rules: Vec<Option<Rule>>,
+}
+
+impl Interactions {
+    pub fn new(interactions: &[Interaction]) -> Self {
+        let phases = interactions
+            .iter()
+            .map(|it| it.phases.0.max(it.phases.1) as usize + 1)
+            .max()
+            .unwrap_or(0);
+        let mut rules = vec![None; phases * phases];
+        for &Interaction {
+            phases: (a, b),
+            rule,
+        } in interactions
+        {
+            let (a, b) = (a as usize, b as usize);
+            rules[a * phases + b] = Some(rule);
+            rules[b * phases + a] = Some(rule);
+        }
+        Self { phases, rules }
+    }
+
+    pub fn rule(&self, first: &Particle, second: &Particle, materials: &[Material]) -> Rule {
+        let (a, b) = (first.phase as usize, second.phase as usize);
+        if a < self.phases && b < self.phases {
+            if let Some(rule) = self.rules[a * self.phases + b] {
+                return rule;
+            }
+        }
+        let fluid = |it: &Particle| scene::material(materials, it.material).fluid.is_some();
+        if fluid(first) && fluid(second) {
+            Rule::Fluid
+        } else {
+            Rule::Contact
+        }
+    }
+}
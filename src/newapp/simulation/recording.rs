@@ -0,0 +1,340 @@
+use std::{io, path::Path};
+
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    super::profiler::Profiler,
+    mouse::Tool,
+    scene::Scene,
+    snapshot::{read_versioned, write_versioned, Snapshot},
+    Simulation,
+};
+
+const MAGIC: [u8; 4] = *b"GZRC";
+// Embeds a snapshot, so bump together with SNAPSHOT_VERSION.
+pub const RECORDING_VERSION: u32 = 1;
+
+/// Everything from outside the solver that can change a run, applied before the step it was queued for.
+#[derive(Serialize, Deserialize, Clone)]
+pub enum Input {
+    ToggleCollisionDetectionMode,
+    ApplyScene(Box<Scene>),
+    MouseMove(Vec2),
+    MouseButton(bool),
+    SelectTool(Tool),
+    Restore(Box<Snapshot>),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Step {
+    pub dt: f32,
+    pub inputs: Vec<Input>,
+    pub spawned: u32,
+    /// By sinks, lifetimes and the mouse
+    pub removed: u32,
+    pub checksum: u64,
+}
+
+/// The staggered row partition depends on the thread count, so it is part of the recording.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Recording {
+    pub num_threads: usize,
+    pub initial: Box<Snapshot>,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Divergence {
+    pub step: usize,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+pub struct Replay {
+    recording: Recording,
+    next_step: usize,
+    first_divergence: Option<Divergence>,
+}
+
+pub enum Session {
+    Live,
+    Recording(Recording),
+    Replay(Replay),
+}
+
+impl Input {
+    pub fn apply(self, simulation: &mut Simulation) {
+        match self {
+            Input::ToggleCollisionDetectionMode => simulation.toggle_collision_detection_mode(),
+            Input::ApplyScene(scene) => simulation.apply_scene(*scene),
+            Input::MouseMove(position) => simulation.on_mouse_move(position),
+            Input::MouseButton(pressed) => simulation.on_mouse_button(pressed),
+            Input::SelectTool(tool) => simulation.select_tool(tool),
+            Input::Restore(snapshot) => simulation.restore(*snapshot),
+        }
+    }
+}
+
+impl Recording {
+    pub fn new(simulation: &Simulation) -> Self {
+        Self {
+            num_threads: simulation.num_threads,
+            initial: Box::new(simulation.snapshot()),
+            steps: vec![],
+        }
+    }
+
+    pub fn record(
+        &mut self,
+        simulation: &mut Simulation,
+        dt: f32,
+        inputs: Vec<Input>,
+        profiler: &mut Profiler,
+    ) {
+        let (spawned, removed) = step(simulation, dt, inputs.clone(), profiler);
+        self.steps.push(Step {
+            dt,
+            inputs,
+            spawned,
+            removed,
+            checksum: checksum(simulation),
+        });
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        write_versioned(path, MAGIC, RECORDING_VERSION, self)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        read_versioned(path, MAGIC, RECORDING_VERSION)
+    }
+}
+
+impl Replay {
+    pub fn new(recording: Recording) -> Self {
+        Self {
+            recording,
+            next_step: 0,
+            first_divergence: None,
+        }
+    }
+
+    /// A simulation in the recorded initial state, replays must start from this.
+    pub fn simulation(&self) -> Simulation {
+        let initial = self.recording.initial.clone();
+        let mut simulation = Simulation::new(initial.scene.clone(), self.recording.num_threads);
+        simulation.restore(*initial);
+        simulation
+    }
+
+    pub fn len(&self) -> usize {
+        self.recording.steps.len()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next_step >= self.recording.steps.len()
+    }
+
+    pub fn first_divergence(&self) -> Option<Divergence> {
+        self.first_divergence
+    }
+
+    /// Runs the next recorded step, returns false once the recording is exhausted.
+    pub fn step(&mut self, simulation: &mut Simulation, profiler: &mut Profiler) -> bool {
+        let Some(recorded) = self.recording.steps.get(self.next_step) else {
+            return false;
+        };
+        let (spawned, removed) = step(simulation, recorded.dt, recorded.inputs.clone(), profiler);
+        let actual = checksum(simulation);
+        if self.first_divergence.is_none()
+            && (actual != recorded.checksum
+                || spawned != recorded.spawned
+                || removed != recorded.removed)
+        {
+            let divergence = Divergence {
+                step: self.next_step,
+                expected: recorded.checksum,
+                actual,
+            };
+            println!(
+                "Replay diverged at step {}: expected checksum {:016x}, got {:016x}, \
+                 spawned {} instead of {}, removed {} instead of {}",
+                divergence.step,
+                divergence.expected,
+                divergence.actual,
+                spawned,
+                recorded.spawned,
+                removed,
+                recorded.removed
+            );
+            self.first_divergence = Some(divergence);
+        }
+        self.next_step += 1;
+        true
+    }
+}
+
+impl Session {
+    /// Replays ignore the live inputs and dt and run the recorded ones instead,
+    /// returns false once a replay is exhausted.
+    pub fn step(
+        &mut self,
+        simulation: &mut Simulation,
+        dt: f32,
+        inputs: Vec<Input>,
+        profiler: &mut Profiler,
+    ) -> bool {
+        match self {
+            Session::Live => {
+                step(simulation, dt, inputs, profiler);
+                true
+            }
+            Session::Recording(recording) => {
+                recording.record(simulation, dt, inputs, profiler);
+                true
+            }
+            Session::Replay(replay) => {
+                let stepped = replay.step(simulation, profiler);
+                if stepped && replay.is_finished() {
+                    match replay.first_divergence() {
+                        Some(divergence) => println!(
+                            "Replay finished, first divergence at step {}",
+                            divergence.step
+                        ),
+                        None => println!(
+                            "Replay of {} steps finished without divergence",
+                            replay.len()
+                        ),
+                    }
+                }
+                stepped
+            }
+        }
+    }
+}
+
+/// The particles spawned and removed by the step.
+fn step(
+    simulation: &mut Simulation,
+    dt: f32,
+    inputs: Vec<Input>,
+    profiler: &mut Profiler,
+) -> (u32, u32) {
+    for input in inputs {
+        input.apply(simulation);
+    }
+    let before = simulation.particles.len();
+    let removed = simulation.removed_particles;
+    simulation.update(dt, profiler);
+    // a step can remove more particles than it spawns
+    let removed = (simulation.removed_particles - removed) as usize;
+    let spawned = simulation.particles.len() + removed - before;
+    (spawned as u32, removed as u32)
+}
+
+/// FNV-1a over the exact bits of the particle state, stable across platforms and compiler versions.
+pub fn checksum(simulation: &Simulation) -> u64 {
+    const PRIME: u64 = 0x100000001b3;
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut write = |value: u64| {
+        for byte in value.to_le_bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(PRIME);
+        }
+    };
+    write(simulation.updates);
+    write(simulation.particles.len() as u64);
+    for particle in &simulation.particles {
+        write(particle.initial_id as u64);
+        write(particle.position.x.to_bits() as u64);
+        write(particle.position.y.to_bits() as u64);
+        write(particle.velocity.x.to_bits() as u64);
+        write(particle.velocity.y.to_bits() as u64);
+        write(particle.radius.to_bits() as u64);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec2;
+
+    use super::{
+        super::{
+            super::profiler::Profiler,
+            mouse::Tool,
+            snapshot::tests::{run, scene},
+            Simulation,
+        },
+        checksum, Input, Recording, Replay,
+    };
+
+    fn inputs(step: usize) -> Vec<Input> {
+        match step {
+            20 => vec![Input::MouseMove(vec2(0.0, -15.0))],
+            35 => vec![Input::ToggleCollisionDetectionMode],
+            // erase part of the block
+            40 => vec![
+                Input::SelectTool(Tool::Delete),
+                Input::MouseMove(vec2(0.0, -24.0)),
+                Input::MouseButton(true),
+            ],
+            45 => vec![Input::MouseButton(false)],
+            _ => vec![],
+        }
+    }
+
+    fn record(num_threads: usize) -> Recording {
+        let mut simulation = Simulation::new(scene(), num_threads);
+        run(&mut simulation, 10);
+        let mut recording = Recording::new(&simulation);
+        let mut profiler = Profiler::new();
+        for step in 0..60 {
+            recording.record(&mut simulation, 1.0 / 60.0, inputs(step), &mut profiler);
+        }
+        recording
+    }
+
+    fn replay(recording: Recording) -> Replay {
+        let mut replay = Replay::new(recording);
+        let mut simulation = replay.simulation();
+        let mut profiler = Profiler::new();
+        while replay.step(&mut simulation, &mut profiler) {}
+        replay
+    }
+
+    #[test]
+    fn replay_of_saved_recording_does_not_diverge() {
+        let path = std::env::temp_dir().join(format!("gamez-recording-{}.rec", std::process::id()));
+        let recording = record(1);
+        let removed = recording.steps.iter().map(|it| it.removed).sum::<u32>();
+        let spawned = recording.steps.iter().map(|it| it.spawned).sum::<u32>();
+        assert!(removed > 0 && spawned > 0);
+        recording.save(&path).unwrap();
+        let loaded = Recording::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let replay = replay(loaded.unwrap());
+        assert!(replay.is_finished());
+        assert!(replay.first_divergence().is_none());
+    }
+
+    #[test]
+    fn multithreaded_runs_are_deterministic() {
+        for num_threads in [2, 3] {
+            let recording = record(num_threads);
+            let final_checksum = recording.steps.last().unwrap().checksum;
+
+            let mut again = Simulation::new(scene(), num_threads);
+            run(&mut again, 10);
+            let mut profiler = Profiler::new();
+            for step in 0..60 {
+                super::step(&mut again, 1.0 / 60.0, inputs(step), &mut profiler);
+            }
+            assert_eq!(checksum(&again), final_checksum);
+
+            assert!(replay(recording).first_divergence().is_none());
+        }
+    }
+}
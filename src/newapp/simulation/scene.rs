@@ -0,0 +1,542 @@
+use std::{f32::consts::PI, fmt::Display, fs, io, path::Path};
+
+use glam::{vec2, UVec2, Vec2};
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    boundary::{closest_on_segment, Wall},
+    box_constraint::BoxConstraint,
+    emitter::{Distribution, Emitter},
+    force_field::ForceField,
+    mouse::MouseTools,
+    obstacle::Obstacle,
+    phase::Interaction,
+    sink::Sink,
+};
+
+pub const DEFAULT_SCENE_FILE: &str = "scenes/default.ron";
+// sizes the grid of scenes that spawn nothing
+const DEFAULT_PARTICLE_RADIUS: f32 = 1.0;
+// smaller cells only cost memory, a tiny radius must not blow up the grid
+const MIN_CELL_SIZE: f32 = 0.5;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Scene {
+    /// Also sizes the collision grid, so it has to enclose the walls
+    pub bounds: BoxConstraint,
+    pub walls: Vec<Wall>,
+    pub obstacles: Vec<Obstacle>,
+    pub sinks: Vec<Sink>,
+    pub gravity: Vec2,
+    pub force_fields: Vec<ForceField>,
+    pub damping: f32,
+    pub max_speed: f32,
+    pub substeps: u32,
+    /// Contact solver iterations per substep
+    pub iterations: u32,
+    pub max_particles: usize,
+    pub seed: u64,
+    pub materials: Vec<Material>,
+    pub interactions: Vec<Interaction>,
+    pub emitters: Vec<Emitter>,
+    pub blocks: Vec<ParticleBlock>,
+    pub chains: Vec<Chain>,
+    pub soft_bodies: Vec<SoftBody>,
+    pub rigid_bodies: Vec<RigidBody>,
+    pub mouse: MouseTools,
+}
+
+/// Referenced by index from emitters, blocks and particles.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Material {
+    /// Mass per unit area, grains are discs so their mass is `density * PI * radius^2`
+    pub density: f32,
+    /// Inverse contact stiffness, 0 is rigid. Compliances of both grains add up for a contact.
+    pub compliance: f32,
+    /// Tangential slip below `static_friction * penetration` is removed entirely
+    pub static_friction: f32,
+    pub dynamic_friction: f32,
+    /// Fraction of the approach speed kept after a bounce, contacts average both grains
+    pub restitution: f32,
+    /// Force pulling touching or nearly touching grains together, contacts average both grains
+    pub cohesion: f32,
+    /// Force pulling grains towards the bounds, walls and obstacles
+    pub adhesion: f32,
+    /// Largest gap cohesion and adhesion reach across
+    pub cohesion_range: f32,
+    /// Makes the particles a liquid at rest at `density`, they no longer collide with each other
+    pub fluid: Option<Fluid>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Fluid {
+    /// Neighbours within this distance make up the density, about twice the particle spacing
+    pub kernel_radius: f32,
+    /// Fraction of the velocity difference to the neighbours removed every substep
+    pub viscosity: f32,
+    /// Acceleration pulling neighbours to half the kernel radius apart
+    pub surface_tension: f32,
+    /// Softens the density constraint, higher is squishier but calmer
+    pub relaxation: f32,
+}
+
+/// Particles laid out on a regular lattice filling `min..max` when the scene is loaded.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ParticleBlock {
+    pub min: Vec2,
+    pub max: Vec2,
+    pub spacing: f32,
+    pub velocity: Vec2,
+    pub radius: (f32, f32),
+    pub material: u32,
+    pub phase: u32,
+}
+
+/// `width` parallel rows of particles from `start` to `end`, `spacing` apart,
+/// each linked to its neighbours along and across the rows.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Chain {
+    pub start: Vec2,
+    pub end: Vec2,
+    pub spacing: f32,
+    pub width: u32,
+    pub radius: f32,
+    pub material: u32,
+    pub phase: u32,
+    pub link: Link,
+    /// Pins the particles at the start and the end in place
+    pub pinned: (bool, bool),
+}
+
+/// A lump of particles that springs back to the shape it was spawned in.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SoftBody {
+    pub center: Vec2,
+    pub lattice: Lattice,
+    pub spacing: f32,
+    pub radius: f32,
+    pub material: u32,
+    pub phase: u32,
+    pub velocity: Vec2,
+    /// Fraction of the deformation undone every substep, 1 is rigid
+    pub stiffness: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Lattice {
+    /// `size` particles wide and high
+    Grid { size: UVec2 },
+    /// `layers` concentric rings, the outermost one of the given radius
+    Ring { radius: f32, layers: u32 },
+}
+
+/// A polygon filled with particles `spacing` apart that moves as a single body. The particles
+/// are centered on the outline, so the body reaches `radius` beyond it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RigidBody {
+    pub position: Vec2,
+    /// Counter-clockwise, in radians
+    pub angle: f32,
+    /// Outline relative to `position`, it doesn't have to be convex
+    pub points: Vec<Vec2>,
+    pub spacing: f32,
+    pub radius: f32,
+    pub material: u32,
+    pub phase: u32,
+    pub velocity: Vec2,
+    /// Radians per second, counter-clockwise
+    pub angular_velocity: f32,
+}
+
+/// How far a joint may stretch, as fractions of its rest length.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Link {
+    pub min: f32,
+    pub max: f32,
+    pub compliance: f32,
+    /// The joint breaks once its force exceeds this, 0 never breaks
+    pub max_force: f32,
+}
+
+pub const DEFAULT_MATERIAL: Material = Material {
+    density: 1.0,
+    compliance: 0.0,
+    static_friction: 0.0,
+    dynamic_friction: 0.0,
+    restitution: 0.0,
+    cohesion: 0.0,
+    adhesion: 0.0,
+    cohesion_range: 0.5,
+    fluid: None,
+};
+
+impl Scene {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let scene: Self =
+            ron::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        scene.validate()?;
+        Ok(scene)
+    }
+
+    /// Rejects the values that would hang or crash the simulation rather than just look odd.
+    pub fn validate(&self) -> io::Result<()> {
+        for (i, emitter) in self.emitters.iter().enumerate() {
+            check_distribution(format_args!("emitter {i}"), "radius", emitter.radius)?;
+            if let Some(lifetime) = emitter.lifetime {
+                check_distribution(format_args!("emitter {i}"), "lifetime", lifetime)?;
+            }
+        }
+        for (i, block) in self.blocks.iter().enumerate() {
+            check_positive(format_args!("block {i}"), "spacing", block.spacing)?;
+            check_positive(format_args!("block {i}"), "radius", block.radius.0)?;
+            check_range(format_args!("block {i}"), "radius", block.radius)?;
+        }
+        for (i, chain) in self.chains.iter().enumerate() {
+            check_positive(format_args!("chain {i}"), "spacing", chain.spacing)?;
+            check_positive(format_args!("chain {i}"), "radius", chain.radius)?;
+        }
+        for (i, body) in self.soft_bodies.iter().enumerate() {
+            check_positive(format_args!("soft body {i}"), "spacing", body.spacing)?;
+            check_positive(format_args!("soft body {i}"), "radius", body.radius)?;
+        }
+        for (i, body) in self.rigid_bodies.iter().enumerate() {
+            check_positive(format_args!("rigid body {i}"), "spacing", body.spacing)?;
+            check_positive(format_args!("rigid body {i}"), "radius", body.radius)?;
+        }
+        for (i, material) in self.materials.iter().enumerate() {
+            check_positive(format_args!("material {i}"), "density", material.density)?;
+        }
+        for (i, field) in self.force_fields.iter().enumerate() {
+            match *field {
+                ForceField::Radial { radius, .. } | ForceField::Vortex { radius, .. } => {
+                    check_positive(format_args!("force field {i}"), "radius", radius)?
+                }
+                ForceField::Noise { scale, .. } => {
+                    check_positive(format_args!("force field {i}"), "scale", scale)?
+                }
+                ForceField::Uniform { .. } | ForceField::Drag { .. } => (),
+            }
+        }
+        check_positive("the mouse paint", "radius", self.mouse.paint_radius.0)?;
+        check_range("the mouse paint", "radius", self.mouse.paint_radius)
+    }
+
+    pub fn max_particle_radius(&self) -> f32 {
+        self.emitters
+            .iter()
+            .map(|it| it.radius.max())
+            .chain(self.blocks.iter().map(|it| it.radius.1))
+            .chain(self.chains.iter().map(|it| it.radius))
+            .chain(self.soft_bodies.iter().map(|it| it.radius))
+            .chain(self.rigid_bodies.iter().map(|it| it.radius))
+            .reduce(f32::max)
+            .unwrap_or(DEFAULT_PARTICLE_RADIUS)
+            .max(self.mouse.paint_radius.1)
+    }
+
+    /// How far past touching particles still pull on each other or on the walls.
+    pub fn max_cohesion_range(&self) -> f32 {
+        self.materials
+            .iter()
+            .filter(|it| it.cohesion > 0.0 || it.adhesion > 0.0)
+            .map(|it| it.cohesion_range)
+            .fold(0.0, f32::max)
+    }
+
+    /// Contacts need cells as wide as the largest particle, fluids as wide as their kernel.
+    pub fn cell_size(&self) -> f32 {
+        self.materials
+            .iter()
+            .filter_map(|it| it.fluid)
+            .map(|it| it.kernel_radius)
+            .fold(
+                self.max_particle_radius() * 2.0 + self.max_cohesion_range(),
+                f32::max,
+            )
+            .max(MIN_CELL_SIZE)
+    }
+
+    pub fn rng(&self) -> ChaCha12Rng {
+        let mut seed = [0u8; 32];
+        seed[..8].copy_from_slice(&self.seed.to_le_bytes());
+        ChaCha12Rng::from_seed(seed)
+    }
+}
+
+fn check_positive(what: impl Display, name: &str, value: f32) -> io::Result<()> {
+    if value > 0.0 {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{what} needs a positive {name}, got {value}"),
+    ))
+}
+
+fn check_range(what: impl Display, name: &str, (min, max): (f32, f32)) -> io::Result<()> {
+    if min <= max {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{what} {name} range {min}..{max} is reversed"),
+    ))
+}
+
+// Normal distributions are cut off, only uniform ones can be reversed.
+fn check_distribution(
+    what: impl Display,
+    name: &str,
+    distribution: Distribution,
+) -> io::Result<()> {
+    match distribution {
+        Distribution::Uniform(min, max) => check_range(what, name, (min, max)),
+        Distribution::Normal { .. } => Ok(()),
+    }
+}
+
+/// Out of range indices fall back to the last material, so a reloaded scene with
+/// fewer materials doesn't invalidate existing particles.
+pub fn material(materials: &[Material], index: u32) -> &Material {
+    materials
+        .get(index as usize)
+        .or(materials.last())
+        .unwrap_or(&DEFAULT_MATERIAL)
+}
+
+impl Material {
+    pub fn inverse_mass(&self, radius: f32) -> f32 {
+        1.0 / (self.density * PI * radius * radius)
+    }
+
+    /// How far adhesion moves a grain of `radius` towards a surface within range in one substep.
+    pub fn adhesion_pull(&self, radius: f32, dt: f32) -> f32 {
+        self.adhesion * self.inverse_mass(radius) * dt * dt
+    }
+}
+
+impl ParticleBlock {
+    pub fn positions(&self) -> impl Iterator<Item = Vec2> + '_ {
+        let count = ((self.max - self.min) / self.spacing).floor().as_uvec2() + 1;
+        (0..count.y).flat_map(move |y| {
+            (0..count.x).map(move |x| self.min + vec2(x as f32, y as f32) * self.spacing)
+        })
+    }
+}
+
+impl Chain {
+    /// Positions row by row, rows are offset to the left of `start..end`.
+    pub fn positions(&self) -> impl Iterator<Item = Vec2> + '_ {
+        let along = self.end - self.start;
+        let count = (along.length() / self.spacing).floor() as u32 + 1;
+        let step = along.normalize_or_zero() * self.spacing;
+        let across = step.perp();
+        (0..self.width.max(1)).flat_map(move |row| {
+            (0..count).map(move |i| self.start + step * i as f32 + across * row as f32)
+        })
+    }
+
+    pub fn length(&self) -> u32 {
+        ((self.end - self.start).length() / self.spacing).floor() as u32 + 1
+    }
+}
+
+impl SoftBody {
+    pub fn positions(&self) -> Vec<Vec2> {
+        match self.lattice {
+            Lattice::Grid { size } => {
+                let corner =
+                    self.center - (size.max(UVec2::ONE) - 1).as_vec2() * self.spacing * 0.5;
+                (0..size.y)
+                    .flat_map(|y| {
+                        (0..size.x).map(move |x| corner + vec2(x as f32, y as f32) * self.spacing)
+                    })
+                    .collect()
+            }
+            Lattice::Ring { radius, layers } => (0..layers)
+                .map(|layer| radius - layer as f32 * self.spacing)
+                .take_while(|radius| *radius > 0.0)
+                .flat_map(|radius| {
+                    let count = ((2.0 * PI * radius / self.spacing).floor() as u32).max(3);
+                    (0..count).map(move |i| {
+                        self.center + radius * Vec2::from_angle(2.0 * PI * i as f32 / count as f32)
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+impl RigidBody {
+    /// Particles along the outline, then a lattice filling the inside.
+    pub fn positions(&self) -> Vec<Vec2> {
+        let rotation = Vec2::from_angle(self.angle);
+        let points = self
+            .points
+            .iter()
+            .map(|it| self.position + rotation.rotate(*it))
+            .collect::<Vec<_>>();
+        let edges = || (0..points.len()).map(|i| (points[i], points[(i + 1) % points.len()]));
+        let mut positions = vec![];
+        for (start, end) in edges() {
+            let count = ((end - start).length() / self.spacing).ceil().max(1.0) as u32;
+            positions.extend((0..count).map(|i| start.lerp(end, i as f32 / count as f32)));
+        }
+        if points.len() < 3 {
+            return positions;
+        }
+        let min = points.iter().copied().fold(Vec2::MAX, Vec2::min);
+        let max = points.iter().copied().fold(Vec2::MIN, Vec2::max);
+        let count = ((max - min) / self.spacing).floor().as_uvec2() + 1;
+        for y in 0..count.y {
+            for x in 0..count.x {
+                let point = min + vec2(x as f32, y as f32) * self.spacing;
+                // even-odd rule, keeping clear of the outline particles
+                let crossings = edges()
+                    .filter(|(start, end)| {
+                        (start.y > point.y) != (end.y > point.y)
+                            && point.x
+                                < start.x
+                                    + (point.y - start.y) / (end.y - start.y) * (end.x - start.x)
+                    })
+                    .count();
+                let clear = edges().all(|(start, end)| {
+                    point.distance(closest_on_segment(start, end, point)) > self.spacing * 0.75
+                });
+                if crossings % 2 == 1 && clear {
+                    positions.push(point);
+                }
+            }
+        }
+        positions
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self {
+            bounds: BoxConstraint::around_center(300.0),
+            walls: vec![],
+            obstacles: vec![],
+            sinks: vec![],
+            gravity: vec2(0.0, -30.0),
+            force_fields: vec![],
+            damping: 0.0,
+            max_speed: 100.0,
+            substeps: 8,
+            iterations: 1,
+            max_particles: 107500,
+            seed: u64::from_le_bytes([1, 2, 3, 4, 0, 0, 0, 0]),
+            materials: vec![DEFAULT_MATERIAL],
+            interactions: vec![],
+            emitters: vec![Emitter::default()],
+            blocks: vec![],
+            chains: vec![],
+            soft_bodies: vec![],
+            rigid_bodies: vec![],
+            mouse: MouseTools::default(),
+        }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        DEFAULT_MATERIAL
+    }
+}
+
+impl Default for Fluid {
+    fn default() -> Self {
+        Self {
+            kernel_radius: 4.0,
+            viscosity: 0.01,
+            surface_tension: 0.0,
+            relaxation: 0.01,
+        }
+    }
+}
+
+impl Default for Chain {
+    fn default() -> Self {
+        Self {
+            start: Vec2::ZERO,
+            end: Vec2::ZERO,
+            spacing: 2.0,
+            width: 1,
+            radius: 1.0,
+            material: 0,
+            phase: 0,
+            link: Link::default(),
+            pinned: (false, false),
+        }
+    }
+}
+
+impl Default for SoftBody {
+    fn default() -> Self {
+        Self {
+            center: Vec2::ZERO,
+            lattice: Lattice::Grid {
+                size: UVec2::splat(5),
+            },
+            spacing: 2.0,
+            radius: 1.0,
+            material: 0,
+            phase: 0,
+            velocity: Vec2::ZERO,
+            stiffness: 0.5,
+        }
+    }
+}
+
+impl Default for RigidBody {
+    fn default() -> Self {
+        Self {
+            position: Vec2::ZERO,
+            angle: 0.0,
+            points: vec![],
+            spacing: 2.0,
+            radius: 1.0,
+            material: 0,
+            phase: 0,
+            velocity: Vec2::ZERO,
+            angular_velocity: 0.0,
+        }
+    }
+}
+
+impl Default for Link {
+    fn default() -> Self {
+        Self {
+            min: 1.0,
+            max: 1.0,
+            compliance: 0.0,
+            max_force: 0.0,
+        }
+    }
+}
+
+impl Default for ParticleBlock {
+    fn default() -> Self {
+        Self {
+            min: Vec2::ZERO,
+            max: Vec2::ZERO,
+            spacing: 2.0,
+            velocity: Vec2::ZERO,
+            radius: (1.0, 1.0),
+            material: 0,
+            phase: 0,
+        }
+    }
+}
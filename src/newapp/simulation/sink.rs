@@ -0,0 +1,18 @@
+use glam::Vec2;
+use serde::{Deserialize, Serialize};
+
+/// Removes every particle whose center enters it, their ids are reused by later spawns.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Sink {
+    Circle { center: Vec2, radius: f32 },
+    Box { min: Vec2, max: Vec2 },
+}
+
+impl Sink {
+    pub fn contains(&self, position: Vec2) -> bool {
+        match *self {
+            Sink::Circle { center, radius } => position.distance_squared(center) < radius * radius,
+            Sink::Box { min, max } => position.cmpge(min).all() && position.cmplt(max).all(),
+        }
+    }
+}
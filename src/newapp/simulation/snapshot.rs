@@ -0,0 +1,155 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use glam::Vec2;
+use rand_chacha::ChaCha12Rng;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{
+    cluster::Cluster, emitter::EmitterState, joint::Joint, mouse::Mouse, scene::Scene,
+    CollisionMode, Color, Particle,
+};
+
+const MAGIC: [u8; 4] = *b"GZSN";
+// Bump whenever any serialized type changes, bincode has no field names to fall back on.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Snapshot {
+    pub scene: Scene,
+    pub particles: Vec<Particle>,
+    pub previous_positions: Vec<Vec2>,
+    pub updates: u64,
+    pub time: f64,
+    pub rng: ChaCha12Rng,
+    pub colors: Vec<Color>,
+    pub collision_detection_mode: CollisionMode,
+    pub joints: Vec<Joint>,
+    pub clusters: Vec<Cluster>,
+    pub free_ids: Vec<usize>,
+    pub mouse: Mouse,
+    pub emitters: Vec<EmitterState>,
+    pub expiry: Vec<f64>,
+}
+
+impl Snapshot {
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        write_versioned(path, MAGIC, SNAPSHOT_VERSION, self)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        read_versioned(path, MAGIC, SNAPSHOT_VERSION)
+    }
+}
+
+pub(super) fn write_versioned<T: Serialize>(
+    path: impl AsRef<Path>,
+    magic: [u8; 4],
+    version: u32,
+    value: &T,
+) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&magic)?;
+    file.write_all(&version.to_le_bytes())?;
+    bincode::serialize_into(&mut file, value)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    file.flush()
+}
+
+pub(super) fn read_versioned<T: DeserializeOwned>(
+    path: impl AsRef<Path>,
+    magic: [u8; 4],
+    version: u32,
+) -> io::Result<T> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut header = [0u8; 8];
+    file.read_exact(&mut header)?;
+    if header[..4] != magic {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("expected a {} file", String::from_utf8_lossy(&magic)),
+        ));
+    }
+    let found = u32::from_le_bytes(header[4..].try_into().unwrap());
+    if found != version {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported file version {found}, expected {version}"),
+        ));
+    }
+    bincode::deserialize_from(&mut file).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use glam::vec2;
+
+    use super::super::{
+        super::profiler::Profiler,
+        box_constraint::BoxConstraint,
+        emitter::{Distribution, Emitter},
+        scene::{ParticleBlock, Scene},
+        sink::Sink,
+        Simulation,
+    };
+
+    /// Spawns, removes and recycles particles within the first second.
+    pub fn scene() -> Scene {
+        Scene {
+            bounds: BoxConstraint::around_center(30.0),
+            max_particles: 400,
+            sinks: vec![Sink::Box {
+                min: vec2(-30.0, -30.0),
+                max: vec2(-20.0, -20.0),
+            }],
+            emitters: vec![Emitter {
+                position: vec2(20.0, 20.0),
+                velocity: vec2(-30.0, 0.0),
+                width: 4,
+                rate: 20.0,
+                lifetime: Some(Distribution::Uniform(0.3, 0.6)),
+                radius: Distribution::Uniform(0.8, 1.0),
+                ..Default::default()
+            }],
+            blocks: vec![ParticleBlock {
+                min: vec2(-10.0, -28.0),
+                max: vec2(10.0, -20.0),
+                radius: (0.8, 1.0),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    pub fn run(simulation: &mut Simulation, updates: u32) {
+        let mut profiler = Profiler::new();
+        for _ in 0..updates {
+            simulation.update(1.0 / 60.0, &mut profiler);
+        }
+    }
+
+    fn state(simulation: &Simulation) -> Vec<u8> {
+        bincode::serialize(&simulation.snapshot()).unwrap()
+    }
+
+    #[test]
+    fn loaded_snapshot_continues_identically() {
+        let mut original = Simulation::new(scene(), 1);
+        run(&mut original, 50);
+
+        let path = std::env::temp_dir().join(format!("gamez-snapshot-{}.bin", std::process::id()));
+        original.save_snapshot(&path).unwrap();
+        let mut loaded = Simulation::new(Scene::default(), 1);
+        let result = loaded.load_snapshot(&path);
+        std::fs::remove_file(&path).unwrap();
+        result.unwrap();
+        assert_eq!(state(&original), state(&loaded));
+
+        run(&mut original, 30);
+        run(&mut loaded, 30);
+        assert_eq!(state(&original), state(&loaded));
+    }
+}
@@ -1,8 +1,11 @@
-use glam::{UVec2, Vec2};
+use glam::{uvec2, UVec2, Vec2};
 pub mod fixed_size_grid;
 pub mod pointer_hash;
 pub mod sorting_hash;
 
+use pointer_hash::PointerHash;
+use sorting_hash::SortingHash;
+
 pub trait SpatialGrid {
     fn size(&self) -> UVec2;
     fn number_of_cells(&self) -> usize;
@@ -12,3 +15,35 @@ pub trait SpatialGrid {
         self.get_cell_index(self.get_cell_coords(position))
     }
 }
+
+/// Whichever hash the current collision mode built, for queries that don't care how
+/// the particles were binned.
+pub enum Neighbourhood<'a, Grid: SpatialGrid> {
+    Pointer(&'a PointerHash<Grid>),
+    Sorting(&'a SortingHash<Grid>),
+}
+
+impl<Grid: SpatialGrid> Neighbourhood<'_, Grid> {
+    /// Calls `f` with the index of every particle in the cell of `position` and the 8 around it.
+    pub fn for_each(&self, position: Vec2, mut f: impl FnMut(usize)) {
+        let grid = match self {
+            Neighbourhood::Pointer(hash) => hash.grid(),
+            Neighbourhood::Sorting(hash) => hash.grid(),
+        };
+        let cell = grid.get_cell_coords(position);
+        let max = grid.size() - 1;
+        let (x_start, x_end) = (cell.x.saturating_sub(1), (cell.x + 1).min(max.x));
+        for y in cell.y.saturating_sub(1)..=(cell.y + 1).min(max.y) {
+            match self {
+                Neighbourhood::Pointer(hash) => (x_start..=x_end)
+                    .flat_map(|x| hash.get_indexes_by_cell(uvec2(x, y)))
+                    .for_each(|&index| f(index)),
+                // a row of cells is one contiguous range
+                Neighbourhood::Sorting(hash) => {
+                    let (start, end) = hash.get_pointers_range(x_start, x_end, y);
+                    (start..end).for_each(&mut f)
+                }
+            }
+        }
+    }
+}
@@ -1,11 +1,47 @@
-use std::{sync::mpsc, time::Duration};
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc,
+};
 
-use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
+use notify::{RecursiveMode, Watcher};
 use winit::event_loop::EventLoopProxy;
 
 use super::application_handler::Event;
 
 pub fn init(event_loop_proxy: &EventLoopProxy<Event>, file_path: &'static str) {
+    watch(
+        event_loop_proxy,
+        file_path.into(),
+        RecursiveMode::Recursive,
+        |_| true,
+        move || Event::FileUpdated(file_path),
+    );
+}
+
+// Editors usually save by replacing the file, which drops a watch on the file itself,
+// so the parent directory is watched and events are filtered by file name.
+pub fn init_scene(event_loop_proxy: &EventLoopProxy<Event>, scene_path: PathBuf) {
+    let directory = match scene_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_owned(),
+        _ => PathBuf::from("."),
+    };
+    let file_name = scene_path.file_name().map(|it| it.to_owned());
+    watch(
+        event_loop_proxy,
+        directory,
+        RecursiveMode::NonRecursive,
+        move |path| file_name.as_deref() == path.file_name(),
+        move || Event::SceneUpdated(scene_path.clone()),
+    );
+}
+
+fn watch(
+    event_loop_proxy: &EventLoopProxy<Event>,
+    path: PathBuf,
+    recursive_mode: RecursiveMode,
+    filter: impl Fn(&Path) -> bool + Send + 'static,
+    event: impl Fn() -> Event + Send + 'static,
+) {
     let event_loop_proxy = event_loop_proxy.clone();
 
     let _handle = std::thread::spawn(move || {
@@ -17,21 +53,18 @@ pub fn init(event_loop_proxy: &EventLoopProxy<Event>, file_path: &'static str) {
         //     Config::default().with_poll_interval(Duration::from_secs(2)),
         // )
         // .unwrap();
-        watcher
-            .watch(file_path.as_ref(), RecursiveMode::Recursive)
-            .unwrap();
+        watcher.watch(&path, recursive_mode).unwrap();
         println!("Unwraped");
 
         for res in rx {
             match dbg!(res) {
                 Ok(notify::Event {
-                    kind: notify::EventKind::Modify(_),
+                    kind: notify::EventKind::Modify(_) | notify::EventKind::Create(_),
+                    paths,
                     ..
-                }) => {
-                    println!("On file changed");
-                    event_loop_proxy
-                        .send_event(Event::FileUpdated(file_path))
-                        .unwrap();
+                }) if paths.iter().any(|it| filter(it)) => {
+                    println!("On file changed {paths:?}");
+                    event_loop_proxy.send_event(event()).unwrap();
                 }
                 Err(e) => println!("Watch error {:?}", e),
                 _ => (),
@@ -8,13 +8,11 @@ pub struct MyRng {
 }
 
 impl MyRng {
-    pub fn new() -> Self {
-        const SEED: [u8; 32] = [
-            1u8, 2u8, 3u8, 4u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-            0, 0, 0, 0, 0, 0,
-        ];
+    pub fn with_seed(seed: u64) -> Self {
+        let mut bytes = [0u8; 32];
+        bytes[..8].copy_from_slice(&seed.to_le_bytes());
         Self {
-            rng: SeedableRng::from_seed(SEED),
+            rng: SeedableRng::from_seed(bytes),
         }
     }
 